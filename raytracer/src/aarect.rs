@@ -43,6 +43,7 @@ impl<M: Material> Hittable for XYRect<M> {
         t_min: f64,
         t_max: f64,
         rec: &mut HitRecord,
+        _rng: &mut RandGen,
     ) -> Option<&dyn Material> {
         let t = (self.k - r.origin().z()) / r.direction().z();
         if t < t_min || t > t_max {
@@ -103,6 +104,7 @@ impl<M: Material> Hittable for XZRect<M> {
         t_min: f64,
         t_max: f64,
         rec: &mut HitRecord,
+        _rng: &mut RandGen,
     ) -> Option<&dyn Material> {
         let t = (self.k - r.origin().y()) / r.direction().y();
         if t < t_min || t > t_max {
@@ -124,10 +126,10 @@ impl<M: Material> Hittable for XZRect<M> {
         Some(&self.mp)
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
         let mut rec = HitRecord::default();
         if self
-            .hit(&Ray::new(o, v, 0.0), 0.001, std::f64::INFINITY, &mut rec)
+            .hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY, &mut rec, rng)
             .is_none()
         {
             return 0.0;
@@ -139,11 +141,11 @@ impl<M: Material> Hittable for XZRect<M> {
 
         distance_squared / (cosine * area)
     }
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
         let random_point = Point3::new(
-            random_double_range(self.x0, self.x1),
+            self.x0 + u.0 * (self.x1 - self.x0),
             self.k,
-            random_double_range(self.z0, self.z1),
+            self.z0 + u.1 * (self.z1 - self.z0),
         );
         random_point - o
     }
@@ -187,6 +189,7 @@ impl<M: Material> Hittable for YZRect<M> {
         t_min: f64,
         t_max: f64,
         rec: &mut HitRecord,
+        _rng: &mut RandGen,
     ) -> Option<&dyn Material> {
         let t = (self.k - r.origin().x()) / r.direction().x();
         if t < t_min || t > t_max {
@@ -208,10 +211,10 @@ impl<M: Material> Hittable for YZRect<M> {
         Some(&self.mp)
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
         let mut rec = HitRecord::default();
         if self
-            .hit(&Ray::new(o, v, 0.0), 0.001, std::f64::INFINITY, &mut rec)
+            .hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY, &mut rec, rng)
             .is_none()
         {
             return 0.0;
@@ -223,11 +226,11 @@ impl<M: Material> Hittable for YZRect<M> {
 
         distance_squared / (cosine * area)
     }
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
         let random_point = Point3::new(
             self.k,
-            random_double_range(self.y0, self.y1),
-            random_double_range(self.z0, self.z1),
+            self.y0 + u.0 * (self.y1 - self.y0),
+            self.z0 + u.1 * (self.z1 - self.z0),
         );
         random_point - o
     }
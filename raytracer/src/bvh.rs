@@ -1,171 +1,359 @@
-use std::cmp::*;
-
 use crate::aabb::*;
 use crate::hittable::*;
 use crate::hittable_list::*;
 use crate::material::*;
 use crate::ray::*;
 use crate::rtweekend::*;
+use crate::vec3::*;
 
-pub struct BvhNode {
-    left: Option<Box<dyn Hittable>>,
-    right: Option<Box<dyn Hittable>>,
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// Relative cost of visiting a node against intersecting one primitive.
+const TRAVERSAL_COST: f64 = 0.125;
+// Past this depth splits fall back to the object median, which bounds the
+// traversal stack.
+const MAX_SAH_DEPTH: usize = 64;
+const STACK_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy)]
+struct LinearNode {
     bbox: Aabb,
+    // Leaf: index of the first object. Interior: index of the second child,
+    // the first one always follows its parent.
+    offset: usize,
+    // Zero for interior nodes.
+    count: usize,
+    axis: usize,
 }
 
-fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> Ordering {
-    let mut box_a: Aabb = Aabb::default();
-    let mut box_b: Aabb = Aabb::default();
+#[derive(Clone, Copy)]
+struct BuildPrimitive {
+    index: usize,
+    bbox: Aabb,
+    centroid: Point3,
+}
 
-    if !a.bounding_box(0.0, 0.0, &mut box_a) || !b.bounding_box(0.0, 0.0, &mut box_b) {
-        println!("No bounding box in bvh_node constructor.");
-    }
+#[derive(Clone, Copy)]
+struct Bin {
+    bbox: Option<Aabb>,
+    count: usize,
+}
 
-    if box_a.min()[axis] < box_b.min()[axis] {
-        Ordering::Less
-    } else if box_a.min()[axis] == box_b.min()[axis] {
-        Ordering::Equal
-    } else {
-        Ordering::Greater
-    }
+fn surface_area(b: &Aabb) -> f64 {
+    let d = b.max() - b.min();
+    2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
 }
 
-fn box_x_compare(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
-    box_compare(a, b, 0)
+fn grow(b: Option<Aabb>, other: &Aabb) -> Option<Aabb> {
+    Some(match b {
+        Some(b) => surrounding_box(&b, other),
+        None => *other,
+    })
 }
 
-fn box_y_compare(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
-    box_compare(a, b, 1)
+fn union_of(prims: &[BuildPrimitive]) -> (Aabb, Aabb) {
+    let mut bbox = prims[0].bbox;
+    let mut centroids = Aabb::new(prims[0].centroid, prims[0].centroid);
+    for p in &prims[1..] {
+        bbox = surrounding_box(&bbox, &p.bbox);
+        centroids = surrounding_box(&centroids, &Aabb::new(p.centroid, p.centroid));
+    }
+    (bbox, centroids)
 }
 
-fn box_z_compare(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
-    box_compare(a, b, 2)
+// Flat bounding volume hierarchy built with the binned surface area heuristic.
+pub struct BvhNode {
+    nodes: Vec<LinearNode>,
+    objects: Vec<Box<dyn Hittable>>,
 }
 
 impl BvhNode {
-    pub fn build(
-        objects: &mut Vec<Box<dyn Hittable>>,
-        start: usize,
-        end: usize,
-        time0: f64,
-        time1: f64,
-    ) -> BvhNode {
-        let axis = random_int(0, 2);
-        let comparator = {
-            if axis == 0 {
-                box_x_compare
-            } else if axis == 1 {
-                box_y_compare
-            } else {
-                box_z_compare
-            }
+    pub fn new(list: &mut HittableList, time0: f64, time1: f64) -> BvhNode {
+        let objects = std::mem::take(&mut list.objects);
+
+        let mut prims: Vec<BuildPrimitive> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let mut bbox = Aabb::default();
+                if !object.bounding_box(time0, time1, &mut bbox) {
+                    println!("No bounding box in bvh_node constructor.");
+                }
+                BuildPrimitive {
+                    index,
+                    bbox,
+                    centroid: 0.5 * (bbox.min() + bbox.max()),
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(2 * prims.len());
+        if !prims.is_empty() {
+            BvhNode::build(&mut prims, 0, 0, &mut nodes);
+        }
+
+        // Reorder the objects so that every leaf owns a contiguous range.
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        let objects = prims
+            .iter()
+            .map(|p| slots[p.index].take().unwrap())
+            .collect();
+
+        BvhNode { nodes, objects }
+    }
+
+    fn build(
+        prims: &mut [BuildPrimitive],
+        first: usize,
+        depth: usize,
+        nodes: &mut Vec<LinearNode>,
+    ) -> usize {
+        let (bbox, centroids) = union_of(prims);
+        let id = nodes.len();
+        nodes.push(LinearNode {
+            bbox,
+            offset: first,
+            count: prims.len(),
+            axis: 0,
+        });
+        if prims.len() == 1 {
+            return id;
+        }
+
+        let extent = centroids.max() - centroids.min();
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
         };
+        if extent[axis] <= 0.0 {
+            // Every centroid coincides, so no split can separate them.
+            if prims.len() <= MAX_LEAF_SIZE {
+                return id;
+            }
+            return BvhNode::split_at(prims, prims.len() / 2, first, depth, axis, id, nodes);
+        }
 
-        let object_span = end - start;
-
-        let left: Option<Box<dyn Hittable>>;
-        let right: Option<Box<dyn Hittable>>;
-
-        if object_span == 1 {
-            left = Some(objects.remove(start));
-            right = None;
-        } else if object_span == 2 {
-            if comparator(&*objects[start], &*objects[start + 1]) == Ordering::Less {
-                right = Some(objects.remove(start + 1));
-                left = Some(objects.remove(start));
-            } else {
-                left = Some(objects.remove(start + 1));
-                right = Some(objects.remove(start));
+        let mid = if depth < MAX_SAH_DEPTH {
+            match BvhNode::sah_split(prims, &bbox, &centroids, axis) {
+                Some(mid) => mid,
+                None => return id,
             }
         } else {
-            objects.sort_by(|x, y| comparator(&**x, &**y));
+            let c_axis = |p: &BuildPrimitive| p.centroid[axis];
+            prims.select_nth_unstable_by(prims.len() / 2, |a, b| total_cmp(c_axis(a), c_axis(b)));
+            prims.len() / 2
+        };
+        BvhNode::split_at(prims, mid, first, depth, axis, id, nodes)
+    }
 
-            let mid = start + object_span / 2;
-            right = Some(Box::new(BvhNode::build(objects, mid, end, time0, time1)));
-            left = Some(Box::new(BvhNode::build(objects, start, mid, time0, time1)));
+    // Partitions prims by the cheapest bin boundary, or returns None when a
+    // leaf is cheaper.
+    fn sah_split(
+        prims: &mut [BuildPrimitive],
+        bbox: &Aabb,
+        centroids: &Aabb,
+        axis: usize,
+    ) -> Option<usize> {
+        let lo = centroids.min()[axis];
+        let scale = BIN_COUNT as f64 / (centroids.max()[axis] - lo);
+        let bin_of =
+            |p: &BuildPrimitive| (((p.centroid[axis] - lo) * scale) as usize).min(BIN_COUNT - 1);
+
+        let mut bins = [Bin {
+            bbox: None,
+            count: 0,
+        }; BIN_COUNT];
+        for p in prims.iter() {
+            let b = &mut bins[bin_of(p)];
+            b.bbox = grow(b.bbox, &p.bbox);
+            b.count += 1;
         }
 
-        let mut box_left: Aabb = Aabb::default();
-        let mut box_right: Aabb = Aabb::default();
+        // Sweep from the right to get the area of every right-hand side.
+        let mut right_area = [0.0; BIN_COUNT];
+        let mut right_count = [0; BIN_COUNT];
+        let mut acc: Option<Aabb> = None;
+        let mut count = 0;
+        for i in (1..BIN_COUNT).rev() {
+            if let Some(b) = &bins[i].bbox {
+                acc = grow(acc, b);
+            }
+            count += bins[i].count;
+            right_area[i] = acc.as_ref().map_or(0.0, surface_area);
+            right_count[i] = count;
+        }
 
-        let left_tag = {
-            if left.is_some() {
-                left.as_ref()
-                    .unwrap()
-                    .bounding_box(time0, time1, &mut box_left)
-            } else {
-                true
+        let mut best: Option<(f64, usize)> = None;
+        let mut acc: Option<Aabb> = None;
+        let mut count = 0;
+        for i in 0..BIN_COUNT - 1 {
+            if let Some(b) = &bins[i].bbox {
+                acc = grow(acc, b);
             }
-        };
-        let right_tag = {
-            if right.is_some() {
-                right
-                    .as_ref()
-                    .unwrap()
-                    .bounding_box(time0, time1, &mut box_right)
-            } else {
-                true
+            count += bins[i].count;
+            if count == 0 || right_count[i + 1] == 0 {
+                continue;
+            }
+            let cost = count as f64 * acc.as_ref().map_or(0.0, surface_area)
+                + right_count[i + 1] as f64 * right_area[i + 1];
+            let better = match best {
+                Some((c, _)) => cost < c,
+                None => true,
+            };
+            if better {
+                best = Some((cost, i));
             }
+        }
+
+        let area = surface_area(bbox);
+        let leaf_cost = prims.len() as f64;
+        let (cost, split) = best?;
+        let split_cost = if area > 0.0 {
+            TRAVERSAL_COST + cost / area
+        } else {
+            TRAVERSAL_COST
         };
-        if !left_tag || !right_tag {
-            println!("No bounding box in bvh_node constructor.");
+        if prims.len() <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+            return None;
         }
 
-        BvhNode {
-            left,
-            right,
-            bbox: surrounding_box(&box_left, &box_right),
+        // In-place partition around the chosen bin boundary.
+        let mut mid = 0;
+        for i in 0..prims.len() {
+            if bin_of(&prims[i]) <= split {
+                prims.swap(i, mid);
+                mid += 1;
+            }
         }
+        Some(mid)
     }
 
-    pub fn new(list: &mut HittableList, time0: f64, time1: f64) -> BvhNode {
-        let size = list.objects.len();
-        BvhNode::build(&mut list.objects, 0, size, time0, time1)
+    fn split_at(
+        prims: &mut [BuildPrimitive],
+        mid: usize,
+        first: usize,
+        depth: usize,
+        axis: usize,
+        id: usize,
+        nodes: &mut Vec<LinearNode>,
+    ) -> usize {
+        let (left, right) = prims.split_at_mut(mid);
+        BvhNode::build(left, first, depth + 1, nodes);
+        let second = BvhNode::build(right, first + mid, depth + 1, nodes);
+        nodes[id].offset = second;
+        nodes[id].count = 0;
+        nodes[id].axis = axis;
+        id
+    }
+}
+
+// Slab test with the reciprocal direction computed once per ray.
+fn hit_box(b: &Aabb, origin: Point3, inv_d: Vec3, t_min: f64, t_max: f64) -> bool {
+    let mut t_min = t_min;
+    let mut t_max = t_max;
+    for a in 0..3 {
+        let mut t0 = (b.minimum[a] - origin[a]) * inv_d[a];
+        let mut t1 = (b.maximum[a] - origin[a]) * inv_d[a];
+        if inv_d[a] < 0.0 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        t_min = t0.max(t_min);
+        t_max = t1.min(t_max);
+        if t_max <= t_min {
+            return false;
+        }
     }
+    true
 }
 
 impl Hittable for BvhNode {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> Option<&dyn Material> {
-        if !self.bbox.hit(r, t_min, t_max) {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        if self.nodes.is_empty() {
             return None;
         }
 
-        let hit_left = {
-            if self.left.is_none() {
-                None
-            } else {
-                self.left.as_ref().unwrap().hit(r, t_min, t_max, rec)
-            }
-        };
-        let hit_right = {
-            if self.right.is_none() {
-                None
-            } else {
-                self.right.as_ref().unwrap().hit(
-                    r,
-                    t_min,
-                    {
-                        if hit_left.is_some() {
-                            rec.t
-                        } else {
-                            t_max
+        let origin = r.origin();
+        let dir = r.direction();
+        let inv_d = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+
+        let mut temp_rec = HitRecord::default();
+        let mut closest_so_far = t_max;
+        let mut hit_anything = None;
+        let mut stack = [0; STACK_SIZE];
+        let mut top = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if hit_box(&node.bbox, origin, inv_d, t_min, closest_so_far) {
+                if node.count > 0 {
+                    for object in &self.objects[node.offset..node.offset + node.count] {
+                        if let Some(mat) = object.hit(r, t_min, closest_so_far, &mut temp_rec, rng)
+                        {
+                            closest_so_far = temp_rec.t;
+                            hit_anything = Some(mat);
+                            *rec = temp_rec;
                         }
-                    },
-                    rec,
-                )
+                    }
+                } else {
+                    // Visit the child nearer along the ray first.
+                    let (near, far) = if dir[node.axis] < 0.0 {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    stack[top] = far;
+                    top += 1;
+                    current = near;
+                    continue;
+                }
             }
-        };
-        if hit_right.is_some() {
-            hit_right
-        } else if hit_left.is_some() {
-            hit_left
-        } else {
-            None
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            current = stack[top];
         }
+        hit_anything
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
-        *output_box = self.bbox;
-        true
+        match self.nodes.first() {
+            Some(node) => {
+                *output_box = node.bbox;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Sampled as a light like a HittableList, with every object equally
+    // likely.
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        let weight = 1.0 / (self.objects.len() as f64);
+        let mut sum = 0.0;
+        for object in &self.objects {
+            sum += weight * object.pdf_value(o, v, rng);
+        }
+        sum
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        let n = self.objects.len();
+        if n == 0 {
+            // Any direction will do, pdf_value is zero for all of them.
+            return Vec3::new(0.0, 1.0, 0.0);
+        }
+        let scaled = u.0 * n as f64;
+        let i = (scaled as usize).min(n - 1);
+        self.objects[i].random(o, ((scaled - i as f64).min(1.0), u.1))
     }
 }
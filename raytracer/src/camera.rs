@@ -1,5 +1,6 @@
 use crate::ray::Ray;
 use crate::rtweekend::*;
+use crate::sampler::*;
 use crate::vec3::*;
 
 #[derive(Clone)]
@@ -51,13 +52,22 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64, time0: f64, time1: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    // Takes a 2D sample for the lens and a 1D one for the time, even for a
+    // pinhole, so later dimensions line up between cameras.
+    pub fn get_ray(
+        &self,
+        s: f64,
+        t: f64,
+        time0: f64,
+        time1: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disk(sampler.get_2d());
         let offset = self.u * rd.x() + self.v * rd.y();
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            random_double_range(time0, time1),
+            time0 + sampler.get_1d() * (time1 - time0),
         )
     }
 }
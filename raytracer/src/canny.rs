@@ -108,33 +108,29 @@ fn non_max_suppression(
 
             let mut pixel_value = magnitude;
             match direction {
-                0..=22 | 157..=180 => {
+                0..=22 | 157..=180
                     if magnitude < gradient_magnitude.get_pixel(x - 1, y)[0]
-                        || magnitude < gradient_magnitude.get_pixel(x + 1, y)[0]
-                    {
-                        pixel_value = 0;
-                    }
+                        || magnitude < gradient_magnitude.get_pixel(x + 1, y)[0] =>
+                {
+                    pixel_value = 0;
                 }
-                23..=67 => {
+                23..=67
                     if magnitude < gradient_magnitude.get_pixel(x - 1, y + 1)[0]
-                        || magnitude < gradient_magnitude.get_pixel(x + 1, y - 1)[0]
-                    {
-                        pixel_value = 0;
-                    }
+                        || magnitude < gradient_magnitude.get_pixel(x + 1, y - 1)[0] =>
+                {
+                    pixel_value = 0;
                 }
-                68..=112 => {
+                68..=112
                     if magnitude < gradient_magnitude.get_pixel(x, y + 1)[0]
-                        || magnitude < gradient_magnitude.get_pixel(x, y - 1)[0]
-                    {
-                        pixel_value = 0;
-                    }
+                        || magnitude < gradient_magnitude.get_pixel(x, y - 1)[0] =>
+                {
+                    pixel_value = 0;
                 }
-                113..=156 => {
+                113..=156
                     if magnitude < gradient_magnitude.get_pixel(x - 1, y - 1)[0]
-                        || magnitude < gradient_magnitude.get_pixel(x + 1, y + 1)[0]
-                    {
-                        pixel_value = 0;
-                    }
+                        || magnitude < gradient_magnitude.get_pixel(x + 1, y + 1)[0] =>
+                {
+                    pixel_value = 0;
                 }
                 _ => {}
             }
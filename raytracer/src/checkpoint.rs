@@ -0,0 +1,217 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+use crate::integrator::*;
+use crate::renderer::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+// A paused render: the command line that started it and everything it has
+// accumulated, with every float stored bit for bit.
+pub struct Checkpoint {
+    pub args: Vec<String>,
+    pub state: RenderState,
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_f64<W: Write>(w: &mut W, v: f64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+// Written next to `path` and then renamed over it, so a save that gets
+// interrupted never leaves a broken checkpoint behind.
+pub fn save<P: AsRef<Path>>(path: P, args: &[String], state: &RenderState) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(prefix) = path.parent() {
+        fs::create_dir_all(prefix)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    let mut w = BufWriter::new(File::create(&temp)?);
+    w.write_all(MAGIC)?;
+    write_u32(&mut w, args.len() as u32)?;
+    for arg in args {
+        write_u32(&mut w, arg.len() as u32)?;
+        w.write_all(arg.as_bytes())?;
+    }
+
+    write_u32(&mut w, state.film.width)?;
+    write_u32(&mut w, state.film.height)?;
+    write_u32(&mut w, state.samples_done as u32)?;
+    write_u64(&mut w, state.passes as u64)?;
+    write_u64(&mut w, state.path_stats.paths)?;
+    write_u64(&mut w, state.path_stats.vertices)?;
+    for (sum, weight) in state.film.sum.iter().zip(&state.film.weight) {
+        for a in 0..3 {
+            write_f64(&mut w, sum[a])?;
+        }
+        write_f64(&mut w, *weight)?;
+    }
+    for stats in &state.stats {
+        write_u32(&mut w, stats.n)?;
+        write_f64(&mut w, stats.mean)?;
+        write_f64(&mut w, stats.m2)?;
+    }
+    w.flush()?;
+    drop(w);
+
+    fs::rename(&temp, path)
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut r = BufReader::new(file);
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        if magic.starts_with(b"RTCKPT") {
+            return Err(invalid_data(
+                "checkpoint from another version of the raytracer",
+            ));
+        }
+        return Err(invalid_data("not a raytracer checkpoint"));
+    }
+
+    let count = read_u32(&mut r)?;
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let mut arg = vec![0; read_u32(&mut r)? as usize];
+        r.read_exact(&mut arg)?;
+        args.push(String::from_utf8(arg).map_err(|_| invalid_data("argument is not UTF-8"))?);
+    }
+
+    let width = read_u32(&mut r)?;
+    let height = read_u32(&mut r)?;
+    let samples_done = read_u32(&mut r)? as i32;
+    let passes = read_u64(&mut r)? as usize;
+    let path_stats = PathStats {
+        paths: read_u64(&mut r)?,
+        vertices: read_u64(&mut r)?,
+    };
+
+    // The pixels must be exactly what is left, which keeps a corrupt size
+    // from allocating anything.
+    let pixel_bytes = 4 * 8 + 4 + 2 * 8;
+    let expected = (width as u64)
+        .checked_mul(height as u64)
+        .and_then(|pixels| pixels.checked_mul(pixel_bytes));
+    if expected != len.checked_sub(r.stream_position()?) {
+        return Err(invalid_data(
+            "checkpoint size does not match its image size",
+        ));
+    }
+    let mut state = RenderState::new(width, height);
+    state.samples_done = samples_done;
+    state.passes = passes;
+    state.path_stats = path_stats;
+    for i in 0..state.stats.len() {
+        state.film.sum[i] = Color::new(read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?);
+        state.film.weight[i] = read_f64(&mut r)?;
+    }
+    for stats in state.stats.iter_mut() {
+        stats.n = read_u32(&mut r)?;
+        stats.mean = read_f64(&mut r)?;
+        stats.m2 = read_f64(&mut r)?;
+    }
+    Ok(Checkpoint { args, state })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut state = RenderState::new(3, 2);
+        state.samples_done = 48;
+        state.passes = 3;
+        state.path_stats = PathStats {
+            paths: 288,
+            vertices: 1013,
+        };
+        for i in 0..state.stats.len() {
+            let x = (i as f64 + 0.1).sqrt();
+            state.film.sum[i] = Color::new(x, -0.0, 1.0 / 3.0 + x);
+            state.film.weight[i] = 47.5 + x;
+            state.stats[i].add(x);
+            state.stats[i].add(0.7 * x);
+        }
+        let args: Vec<String> = vec![
+            "render".into(),
+            "scene.yaml".into(),
+            "--spp".into(),
+            "ünï".into(),
+        ];
+
+        let path = std::env::temp_dir().join(format!("raytracer-{}.ckpt", std::process::id()));
+        save(&path, &args, &state).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.args, args);
+        assert_eq!((loaded.state.film.width, loaded.state.film.height), (3, 2));
+        let bits = |v: &[Color]| -> Vec<u64> {
+            v.iter()
+                .flat_map(|c| vec![c.x().to_bits(), c.y().to_bits(), c.z().to_bits()])
+                .collect()
+        };
+        assert_eq!(bits(&loaded.state.film.sum), bits(&state.film.sum));
+        assert_eq!(loaded.state.film.weight, state.film.weight);
+        assert_eq!(loaded.state.stats, state.stats);
+        assert_eq!(loaded.state.samples_done, state.samples_done);
+        assert_eq!(loaded.state.passes, state.passes);
+        assert_eq!(loaded.state.path_stats, state.path_stats);
+    }
+
+    #[test]
+    fn load_rejects_a_size_that_does_not_match() {
+        let args = vec![String::from("render")];
+        let path = std::env::temp_dir().join(format!("raytracer-{}-size.ckpt", std::process::id()));
+        save(&path, &args, &RenderState::new(4, 3)).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        // Cut short, and with a width far beyond what the file holds.
+        let truncated = &bytes[..bytes.len() - 1];
+        let mut huge = bytes.clone();
+        let width_at = MAGIC.len() + 4 + 4 + args[0].len();
+        huge[width_at..width_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        for corrupt in &[truncated, &huge[..]] {
+            fs::write(&path, corrupt).unwrap();
+            let err = load(&path).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use image::RgbImage;
 
-use crate::rtweekend::clamp;
+use crate::tonemap::ToneMapper;
 use crate::vec3::Color;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -15,33 +15,16 @@ impl Position {
     }
 }
 
-pub fn write_color(img: &mut RgbImage, pos: Position, rgb: Color, samples_per_pixel: i32) {
+pub fn write_color(img: &mut RgbImage, pos: Position, rgb: Color, tone: &ToneMapper) {
     let pixel = img.get_pixel_mut(pos.x, pos.y);
-    let mut r: f64 = rgb.x();
-    let mut g: f64 = rgb.y();
-    let mut b: f64 = rgb.z();
-
-    if r.is_nan() {
-        r = 0.0;
-    }
-    if g.is_nan() {
-        g = 0.0;
-    }
-    if b.is_nan() {
-        b = 0.0;
+    let mut c = rgb;
+    for a in 0..3 {
+        if c[a].is_nan() {
+            c[a] = 0.0;
+        }
     }
 
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let scale = 1.0 / (samples_per_pixel as f64);
-    r = (r * scale).sqrt();
-    g = (g * scale).sqrt();
-    b = (b * scale).sqrt();
-
-    *pixel = image::Rgb([
-        (256.0 * clamp(r, 0.0, 0.999)) as u8,
-        (256.0 * clamp(g, 0.0, 0.999)) as u8,
-        (256.0 * clamp(b, 0.0, 0.999)) as u8,
-    ]);
+    *pixel = image::Rgb(tone.to_srgb8(c));
 }
 
 pub fn read_color(img: &RgbImage, pos: Position) -> Color {
@@ -51,3 +34,46 @@ pub fn read_color(img: &RgbImage, pos: Position) -> Color {
     let b = (*pixel)[2];
     Color::new(r as f64, g as f64, b as f64)
 }
+
+// Relative luminance of a linear sRGB color.
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+// CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    Color::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+// Color of a black body at the given temperature in kelvin, scaled to unit
+// luminance. Colors sRGB can't show are clipped.
+pub fn blackbody(temperature: f64) -> Color {
+    // Wyman, Sloan and Shirley's fit of the CIE 1931 matching functions.
+    let lobe = |lambda: f64, mu: f64, below: f64, above: f64| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    let mut lambda: f64 = 380.0;
+    while lambda <= 780.0 {
+        // Planck's law, up to a constant factor, with lambda in nm.
+        let planck = lambda.powi(-5) / ((1.4388e7 / (lambda * temperature)).exp() - 1.0);
+        x += planck
+            * (1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+                - 0.065 * lobe(lambda, 501.1, 20.4, 26.2));
+        y += planck
+            * (0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1));
+        z += planck
+            * (1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8));
+        lambda += 5.0;
+    }
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let rgb = xyz_to_rgb(x / y, 1.0, z / y);
+    Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+}
@@ -1,6 +1,3 @@
-use std::f64::INFINITY;
-use std::f64::NEG_INFINITY;
-
 use crate::hittable::*;
 use crate::material::*;
 use crate::rtweekend::*;
@@ -14,10 +11,10 @@ pub struct ConstantMedium<H: Hittable, M: Material> {
 }
 
 impl<H: Hittable, T: Texture> ConstantMedium<H, Isotropic<T>> {
-    pub fn _mv(boundary: H, d: f64, a: T) -> ConstantMedium<H, Isotropic<T>> {
+    pub fn mv(boundary: H, d: f64, a: T) -> ConstantMedium<H, Isotropic<T>> {
         ConstantMedium {
             boundary,
-            phase_function: Isotropic::_mv(a),
+            phase_function: Isotropic::mv(a),
             neg_inv_density: -1.0 / d,
         }
     }
@@ -42,12 +39,15 @@ impl<H: Hittable, M: Material> Hittable for ConstantMedium<H, M> {
         t_min: f64,
         t_max: f64,
         rec: &mut HitRecord,
+        rng: &mut RandGen,
     ) -> Option<&dyn Material> {
         let mut rec1 = HitRecord::default();
         let mut rec2 = HitRecord::default();
 
-        self.boundary.hit(r, NEG_INFINITY, INFINITY, &mut rec1)?;
-        self.boundary.hit(r, rec1.t + 0.0001, INFINITY, &mut rec2)?;
+        self.boundary
+            .hit(r, f64::NEG_INFINITY, f64::INFINITY, &mut rec1, rng)?;
+        self.boundary
+            .hit(r, rec1.t + 0.0001, f64::INFINITY, &mut rec2, rng)?;
 
         if rec1.t < t_min {
             rec1.t = t_min;
@@ -66,7 +66,7 @@ impl<H: Hittable, M: Material> Hittable for ConstantMedium<H, M> {
 
         let ray_length = r.direction().length();
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_double().ln();
+        let hit_distance = self.neg_inv_density * random_double(rng).ln();
 
         if hit_distance > distance_inside_boundary {
             return None;
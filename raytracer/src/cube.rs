@@ -3,6 +3,7 @@ use crate::aarect::*;
 use crate::hittable::*;
 use crate::hittable_list::*;
 use crate::material::*;
+use crate::rtweekend::*;
 use crate::vec3::*;
 
 pub struct Cube {
@@ -12,7 +13,7 @@ pub struct Cube {
 }
 
 impl Cube {
-    pub fn new<M: 'static + Material + Copy>(p0: Point3, p1: Point3, ptr: M) -> Cube {
+    pub fn new<M: 'static + Material + Clone>(p0: Point3, p1: Point3, ptr: M) -> Cube {
         let mut sides = HittableList::default();
         sides.add(Box::new(XYRect::new(
             p0.x(),
@@ -20,7 +21,7 @@ impl Cube {
             p0.y(),
             p1.y(),
             p1.z(),
-            ptr,
+            ptr.clone(),
         )));
         sides.add(Box::new(XYRect::new(
             p0.x(),
@@ -28,7 +29,7 @@ impl Cube {
             p0.y(),
             p1.y(),
             p0.z(),
-            ptr,
+            ptr.clone(),
         )));
 
         sides.add(Box::new(XZRect::new(
@@ -37,7 +38,7 @@ impl Cube {
             p0.z(),
             p1.z(),
             p1.y(),
-            ptr,
+            ptr.clone(),
         )));
         sides.add(Box::new(XZRect::new(
             p0.x(),
@@ -45,7 +46,7 @@ impl Cube {
             p0.z(),
             p1.z(),
             p0.y(),
-            ptr,
+            ptr.clone(),
         )));
 
         sides.add(Box::new(YZRect::new(
@@ -54,7 +55,7 @@ impl Cube {
             p0.z(),
             p1.z(),
             p1.x(),
-            ptr,
+            ptr.clone(),
         )));
         sides.add(Box::new(YZRect::new(
             p0.y(),
@@ -83,7 +84,8 @@ impl Hittable for Cube {
         t_min: f64,
         t_max: f64,
         rec: &mut HitRecord,
+        rng: &mut RandGen,
     ) -> Option<&dyn Material> {
-        self.sides.hit(r, t_min, t_max, rec)
+        self.sides.hit(r, t_min, t_max, rec, rng)
     }
 }
@@ -0,0 +1,259 @@
+// Piecewise-constant densities over [0,1) and [0,1)^2, sampled by inverting
+// their CDFs.
+
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[f64]) -> Distribution1D {
+        let n = func.len();
+        let func: Vec<f64> = func.iter().map(|f| f.abs()).collect();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        if integral == 0.0 {
+            // Nothing to prefer, so fall back to uniform.
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f64 / n as f64;
+            }
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // Density at x of what sample_continuous draws.
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.count();
+        let i = ((x * n as f64) as usize).min(n - 1);
+        if self.integral == 0.0 {
+            1.0
+        } else {
+            self.func[i] / self.integral
+        }
+    }
+
+    // Returns x, its density and the segment it is in.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        // Last i with cdf[i] <= u, but never past the final segment.
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = ((i as f64 + du) / n as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf(x), i)
+    }
+}
+
+// Picks v from the marginal density of the rows, then u from that row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // func holds nv rows of nu values, row after row.
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| Distribution1D::new(&func[v * nu..(v + 1) * nu]))
+            .collect();
+        let marginal: Vec<f64> = conditional.iter().map(|d| d.integral()).collect();
+        Distribution2D {
+            conditional,
+            marginal: Distribution1D::new(&marginal),
+        }
+    }
+
+    // Returns (u, v) and its density.
+    pub fn sample_continuous(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u.1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u.0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let nv = self.conditional.len();
+        let row = ((v * nv as f64) as usize).min(nv - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+// Walker's alias method, as set up by Vose: picks one of n items by weight in
+// constant time.
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    // Chance of keeping the bin's own item rather than its alias.
+    keep: Vec<f64>,
+    alias: Vec<usize>,
+    pmf: Vec<f64>,
+}
+
+impl AliasTable {
+    // Items of zero weight are never picked, unless all of them are.
+    pub fn new(weights: &[f64]) -> AliasTable {
+        let n = weights.len();
+        let total: f64 = weights.iter().map(|w| w.max(0.0)).sum();
+        let pmf: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w.max(0.0) / total).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        let mut keep: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let mut alias: Vec<usize> = (0..n).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| keep[i] < 1.0);
+        while !small.is_empty() && !large.is_empty() {
+            // The large item fills what is left of the small one's bin.
+            let s = small.pop().unwrap();
+            let l = *large.last().unwrap();
+            alias[s] = l;
+            keep[l] -= 1.0 - keep[s];
+            if keep[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Whatever is left is full, up to rounding.
+        for i in small.into_iter().chain(large) {
+            keep[i] = 1.0;
+        }
+        AliasTable { keep, alias, pmf }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pmf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pmf.is_empty()
+    }
+
+    pub fn pmf(&self, i: usize) -> f64 {
+        self.pmf[i]
+    }
+
+    // Returns the item, its probability and u stretched back to [0,1).
+    pub fn sample(&self, u: f64) -> (usize, f64, f64) {
+        let n = self.len();
+        let scaled = u * n as f64;
+        let bin = (scaled as usize).min(n - 1);
+        let up = (scaled - bin as f64).min(1.0);
+        let keep = self.keep[bin];
+        let (i, u_rest) = if up < keep {
+            (bin, up / keep)
+        } else {
+            (self.alias[bin], ((up - keep) / (1.0 - keep)).min(1.0))
+        };
+        (i, self.pmf[i], u_rest.min(1.0 - f64::EPSILON))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::*;
+
+    const SAMPLES: usize = 200_000;
+
+    #[test]
+    fn alias_table_matches_weights() {
+        let weights = [3.0, 0.0, 1.0, 6.0, 0.5, 2.5];
+        let table = AliasTable::new(&weights);
+        let total: f64 = weights.iter().sum();
+        let sum: f64 = (0..table.len()).map(|i| table.pmf(i)).sum();
+        assert!((sum - 1.0).abs() < 1e-12);
+
+        let rng = &mut sample_rng(1, 0, 0);
+        let mut counts = vec![0; table.len()];
+        for _ in 0..SAMPLES {
+            let (i, p, u) = table.sample(random_double(rng));
+            assert_eq!(p, table.pmf(i));
+            assert!((0.0..=1.0).contains(&u));
+            counts[i] += 1;
+        }
+        for (i, w) in weights.iter().enumerate() {
+            assert!((table.pmf(i) - w / total).abs() < 1e-12);
+            let frequency = counts[i] as f64 / SAMPLES as f64;
+            assert!((frequency - table.pmf(i)).abs() < 0.005, "item {}", i);
+        }
+        assert_eq!(counts[1], 0);
+    }
+
+    #[test]
+    fn distribution_2d_matches_pdf() {
+        let (nu, nv) = (8, 4);
+        let func: Vec<f64> = (0..nu * nv)
+            .map(|i| {
+                if i % 5 == 0 {
+                    0.0
+                } else {
+                    (i % 7) as f64 + 0.5
+                }
+            })
+            .collect();
+        let dist = Distribution2D::new(&func, nu, nv);
+        let cell = |(u, v): (f64, f64)| {
+            let x = ((u * nu as f64) as usize).min(nu - 1);
+            let y = ((v * nv as f64) as usize).min(nv - 1);
+            y * nu + x
+        };
+        let center = |i: usize| {
+            (
+                ((i % nu) as f64 + 0.5) / nu as f64,
+                ((i / nu) as f64 + 0.5) / nv as f64,
+            )
+        };
+
+        // The pdf is constant over each cell, so this is its integral.
+        let area = 1.0 / (nu * nv) as f64;
+        let integral: f64 = (0..nu * nv)
+            .map(|i| {
+                let (u, v) = center(i);
+                dist.pdf(u, v) * area
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-12);
+
+        let rng = &mut sample_rng(2, 0, 0);
+        let mut counts = vec![0; nu * nv];
+        for _ in 0..SAMPLES {
+            let (p, pdf) = dist.sample_continuous((random_double(rng), random_double(rng)));
+            assert!((pdf - dist.pdf(p.0, p.1)).abs() < 1e-9);
+            counts[cell(p)] += 1;
+        }
+        for (i, &count) in counts.iter().enumerate() {
+            let (u, v) = center(i);
+            let frequency = count as f64 / SAMPLES as f64;
+            assert!(
+                (frequency - dist.pdf(u, v) * area).abs() < 0.005,
+                "cell {}",
+                i
+            );
+        }
+    }
+}
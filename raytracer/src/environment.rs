@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use image::codecs::hdr::HdrDecoder;
+use image::ImageError;
+
+use crate::color::*;
+use crate::distribution::*;
+use crate::pdf::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// What a ray that escapes the scene sees.
+pub enum Background {
+    Color(Color),
+    Environment(EnvironmentMap),
+}
+
+impl Background {
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        match self {
+            Background::Color(color) => *color,
+            Background::Environment(map) => map.radiance(direction),
+        }
+    }
+
+    // The part of the background that can be light sampled.
+    pub fn environment(&self) -> Option<&EnvironmentMap> {
+        match self {
+            Background::Color(_) => None,
+            Background::Environment(map) => Some(map),
+        }
+    }
+}
+
+// Radiance from infinitely far away, stored as a lat-long image whose top row
+// looks up +y. Pixels are constant, which is also what sampling assumes.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    // Degrees about +y.
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<Color>,
+        rotation: f64,
+        intensity: f64,
+    ) -> EnvironmentMap {
+        assert!(
+            width > 0 && height > 0 && pixels.len() == width * height,
+            "Environment map does not match its size"
+        );
+        // Negative, infinite or NaN texels would break the distribution.
+        let finite = |x: f64| if x.is_finite() { x.max(0.0) } else { 0.0 };
+        let pixels: Vec<Color> = pixels
+            .iter()
+            .map(|c| Color::new(finite(c.x()), finite(c.y()), finite(c.z())))
+            .collect();
+        // Rows near the poles cover less solid angle.
+        let mut func = Vec::with_capacity(pixels.len());
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                let c = pixels[y * width + x];
+                func.push(luminance(c) * sin_theta);
+            }
+        }
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation,
+            intensity,
+            distribution: Distribution2D::new(&func, width, height),
+        }
+    }
+
+    // Radiance given as a function of direction, taken at texel centers.
+    pub fn bake<F: Fn(Vec3) -> Color>(
+        width: usize,
+        height: usize,
+        intensity: f64,
+        radiance: F,
+    ) -> EnvironmentMap {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f64 + 0.5) / width as f64;
+                let v = (y as f64 + 0.5) / height as f64;
+                pixels.push(radiance(lat_long_direction(u, v)));
+            }
+        }
+        EnvironmentMap::new(width, height, pixels, 0.0, intensity)
+    }
+
+    // Loads a Radiance .hdr or an uncompressed scanline OpenEXR file.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        rotation: f64,
+        intensity: f64,
+    ) -> io::Result<EnvironmentMap> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let (width, height, pixels) = match extension.as_deref() {
+            Some("hdr") => read_hdr(path)?,
+            Some("exr") => read_exr(&std::fs::read(path)?)?,
+            _ => return Err(invalid_data("expected an .hdr or .exr file")),
+        };
+        Ok(EnvironmentMap::new(
+            width, height, pixels, rotation, intensity,
+        ))
+    }
+
+    // Map coordinates in [0,1)^2 of a world direction.
+    fn uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = unit_vector(direction);
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = (-d.z()).atan2(d.x()) + PI - degrees_to_radians(self.rotation);
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let (u, v) = self.uv(direction);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.intensity * self.pixels[y * self.width + x]
+    }
+
+    // Density over solid angle of the directions sample draws.
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        let (u, v) = self.uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    // Brighter parts of the map are picked more often.
+    pub fn sample(&self, u: (f64, f64)) -> Vec3 {
+        let ((u, v), _) = self.distribution.sample_continuous(u);
+        let rotation = degrees_to_radians(self.rotation) / (2.0 * PI);
+        lat_long_direction(u + rotation, v)
+    }
+}
+
+// Light sampling of the environment, the same from wherever it is looked at.
+pub struct EnvironmentPdf<'a> {
+    map: &'a EnvironmentMap,
+}
+impl<'a> EnvironmentPdf<'a> {
+    pub fn new(map: &'a EnvironmentMap) -> EnvironmentPdf<'a> {
+        EnvironmentPdf { map }
+    }
+}
+impl<'a> Pdf for EnvironmentPdf<'a> {
+    fn value(&self, direction: Vec3, _rng: &mut RandGen) -> f64 {
+        self.map.pdf(direction)
+    }
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        self.map.sample(u)
+    }
+}
+
+// Direction of map coordinates (u, v), with no rotation.
+fn lat_long_direction(u: f64, v: f64) -> Vec3 {
+    let theta = v * PI;
+    let phi = u * 2.0 * PI - PI;
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        -theta.sin() * phi.sin(),
+    )
+}
+
+type Pixels = (usize, usize, Vec<Color>);
+
+fn read_hdr(path: &Path) -> io::Result<Pixels> {
+    let to_io = |err| match err {
+        ImageError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    };
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?)).map_err(to_io)?;
+    let meta = decoder.metadata();
+    let data = decoder.read_image_hdr().map_err(to_io)?;
+    let pixels = data
+        .iter()
+        .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+        .collect();
+    Ok((meta.width as usize, meta.height as usize, pixels))
+}
+
+// Little-endian fields of an in-memory file.
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(invalid_data("truncated file"));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> io::Result<&'a str> {
+        let rest = &self.data[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_data("truncated file"))?;
+        self.pos += len + 1;
+        std::str::from_utf8(&rest[..len]).map_err(|_| invalid_data("bad attribute name"))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let b = self.take(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    // One channel value, widened to f32.
+    fn value(&mut self, pixel_type: i32) -> io::Result<f32> {
+        Ok(match pixel_type {
+            0 => self.i32()? as u32 as f32,
+            1 => {
+                let b = self.take(2)?;
+                half_to_f32(u16::from_le_bytes([b[0], b[1]]))
+            }
+            _ => f32::from_bits(self.i32()? as u32),
+        })
+    }
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// The subset of OpenEXR that write_exr produces: one part, scanlines, no
+// compression. Channels may be half or float; a lone Y channel is gray.
+fn read_exr(data: &[u8]) -> io::Result<Pixels> {
+    let mut bytes = Bytes { data, pos: 0 };
+    if bytes.take(4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return Err(invalid_data("not an OpenEXR file"));
+    }
+    let version = bytes.i32()?;
+    if version & 0xff != 2 {
+        return Err(invalid_data("unsupported OpenEXR version"));
+    }
+    // Tiled, deep and multi-part files.
+    if version & (0x200 | 0x800 | 0x1000) != 0 {
+        return Err(invalid_data("only scanline OpenEXR files are supported"));
+    }
+
+    let mut channels = Vec::new();
+    let mut window = None;
+    loop {
+        let name = bytes.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = bytes.string()?;
+        let size = bytes.i32()?;
+        if size < 0 {
+            return Err(invalid_data("bad attribute size"));
+        }
+        let mut value = Bytes {
+            data: bytes.take(size as usize)?,
+            pos: 0,
+        };
+        match (name, kind) {
+            ("channels", "chlist") => loop {
+                let channel = value.string()?;
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = value.i32()?;
+                value.take(4)?;
+                if value.i32()? != 1 || value.i32()? != 1 {
+                    return Err(invalid_data("subsampled channels are not supported"));
+                }
+                channels.push((channel, pixel_type));
+            },
+            ("compression", _) if value.take(1)?[0] != 0 => {
+                return Err(invalid_data("compressed OpenEXR files are not supported"));
+            }
+            ("dataWindow", "box2i") => {
+                let x0 = value.i32()?;
+                let y0 = value.i32()?;
+                let x1 = value.i32()?;
+                let y1 = value.i32()?;
+                window = Some((x0, y0, x1, y1));
+            }
+            _ => {}
+        }
+    }
+
+    let (x0, y0, x1, y1) = window.ok_or_else(|| invalid_data("missing dataWindow"))?;
+    if x1 < x0 || y1 < y0 {
+        return Err(invalid_data("empty image"));
+    }
+    let width = (x1 - x0 + 1) as usize;
+    let height = (y1 - y0 + 1) as usize;
+    let index: HashMap<&str, usize> = channels
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, i))
+        .collect();
+    let rgb = match (
+        index.get("R"),
+        index.get("G"),
+        index.get("B"),
+        index.get("Y"),
+    ) {
+        (Some(&r), Some(&g), Some(&b), _) => [r, g, b],
+        (_, _, _, Some(&y)) => [y, y, y],
+        _ => return Err(invalid_data("expected R, G and B channels")),
+    };
+
+    let mut pixels = vec![Color::new(0.0, 0.0, 0.0); width * height];
+    let mut line = vec![0.0; width * channels.len()];
+    for _ in 0..height {
+        let offset = bytes.u64()?;
+        if offset as usize >= data.len() {
+            return Err(invalid_data("truncated file"));
+        }
+        let mut chunk = Bytes {
+            data,
+            pos: offset as usize,
+        };
+        let y = chunk.i32()? - y0;
+        if y < 0 || y as usize >= height {
+            return Err(invalid_data("scanline outside the image"));
+        }
+        chunk.i32()?;
+        for (c, (_, pixel_type)) in channels.iter().enumerate() {
+            for x in 0..width {
+                line[c * width + x] = chunk.value(*pixel_type)?;
+            }
+        }
+        for x in 0..width {
+            let at = |c: usize| line[c * width + x] as f64;
+            pixels[y as usize * width + x] = Color::new(at(rgb[0]), at(rgb[1]), at(rgb[2]));
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::*;
+
+    #[test]
+    fn reads_back_what_write_exr_wrote() {
+        let (width, height) = (3, 2);
+        let mut film = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = Color::new(x as f64 + 0.25, y as f64 * 8.0, 1.5 / (x + y + 1) as f64);
+                film.add(x, y, c, 1.0);
+            }
+        }
+        let mut data = Vec::new();
+        film.write_exr(&mut data).unwrap();
+
+        let (w, h, pixels) = read_exr(&data).unwrap();
+        assert_eq!((w, h), (width as usize, height as usize));
+        for y in 0..height {
+            for x in 0..width {
+                let expected = film.pixel(x, y);
+                let read = pixels[y as usize * w + x as usize];
+                for a in 0..3 {
+                    assert_eq!(read[a], expected[a] as f32 as f64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_what_is_not_exr() {
+        assert!(read_exr(b"P6\n1 1\n255\n").is_err());
+        let mut data = Vec::new();
+        Framebuffer::new(2, 2).write_exr(&mut data).unwrap();
+        assert!(read_exr(&data[..data.len() - 1]).is_err());
+    }
+}
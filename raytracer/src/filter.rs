@@ -0,0 +1,115 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            _ => Err(format!(
+                "unknown filter \"{}\", expected box, tent, gaussian or mitchell",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FilterKind {
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+        }
+    }
+}
+
+// Mitchell-Netravali cubic with B = C = 1/3, for x in [-2,2].
+fn mitchell_1d(x: f64) -> f64 {
+    let b = 1.0 / 3.0;
+    let c = 1.0 / 3.0;
+    let x = x.abs();
+    if x > 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+// Separable pixel reconstruction filter, with the radius in pixels. Weights
+// need not integrate to one since the film divides by their sum.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        // Half open, so a box filter puts every sample in exactly one pixel.
+        if x < -self.radius || x >= self.radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => self.radius - x.abs(),
+            FilterKind::Gaussian => {
+                // Shifted down so that it reaches zero at the radius.
+                let alpha = 2.0;
+                (-alpha * x * x).exp() - (-alpha * self.radius * self.radius).exp()
+            }
+            FilterKind::Mitchell => mitchell_1d(2.0 * x / self.radius),
+        }
+    }
+
+    // Weight of a sample at offset (dx, dy) from a pixel center.
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
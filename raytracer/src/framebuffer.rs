@@ -0,0 +1,235 @@
+use std::io::{self, Write};
+
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, ImageError, Rgb, RgbImage};
+
+use crate::color::*;
+use crate::filter::*;
+use crate::tonemap::*;
+use crate::vec3::*;
+
+// Linear radiance accumulated per pixel. Nothing is clamped or quantized
+// until an 8-bit image is asked for.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub(crate) sum: Vec<Color>,
+    pub(crate) weight: Vec<f64>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        let size = width as usize * height as usize;
+        Framebuffer {
+            width,
+            height,
+            sum: vec![Color::default(); size],
+            weight: vec![0.0; size],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn add(&mut self, x: u32, y: u32, color: Color, weight: f64) {
+        let i = self.index(x, y);
+        self.sum[i] += color;
+        self.weight[i] += weight;
+    }
+
+    // Adds a tile's splats, weights included.
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let j = tile.index(x, y);
+                let i = self.index(x, y);
+                self.sum[i] += tile.sum[j];
+                self.weight[i] += tile.weight[j];
+            }
+        }
+    }
+
+    // Average radiance of a pixel.
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        if self.weight[i] <= 0.0 {
+            return Color::default();
+        }
+        self.sum[i] / self.weight[i]
+    }
+
+    pub fn to_rgb8(&self, tone: &ToneMapper) -> RgbImage {
+        let mut img: RgbImage = ImageBuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                write_color(&mut img, Position::pos(y, x), self.pixel(x, y), tone);
+            }
+        }
+        img
+    }
+
+    fn to_f32(&self) -> Vec<[f32; 3]> {
+        let mut data = Vec::with_capacity(self.sum.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = self.pixel(x, y);
+                data.push([c.x() as f32, c.y() as f32, c.z() as f32]);
+            }
+        }
+        data
+    }
+
+    // Radiance RGBE, as written by the image crate.
+    pub fn write_hdr<W: Write>(&self, w: W) -> io::Result<()> {
+        let data: Vec<Rgb<f32>> = self.to_f32().into_iter().map(Rgb).collect();
+        HdrEncoder::new(w)
+            .encode(&data, self.width as usize, self.height as usize)
+            .map_err(|err| match err {
+                ImageError::IoError(err) => err,
+                err => io::Error::new(io::ErrorKind::InvalidInput, err),
+            })
+    }
+
+    // Single-part scanline OpenEXR with uncompressed 32-bit float R, G, B.
+    pub fn write_exr<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let width = self.width as i32;
+        let height = self.height as i32;
+
+        let mut channels = Vec::new();
+        // Channels are stored in alphabetical order.
+        for name in &["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+            channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+        let mut window = Vec::new();
+        for v in &[0, 0, width - 1, height - 1] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        add_attribute(&mut header, "channels", "chlist", &channels);
+        add_attribute(&mut header, "compression", "compression", &[0]);
+        add_attribute(&mut header, "dataWindow", "box2i", &window);
+        add_attribute(&mut header, "displayWindow", "box2i", &window);
+        add_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        add_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        add_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        add_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+        w.write_all(&header)?;
+
+        // One scanline per chunk, so the offset table has an entry per row.
+        let line_size = 3 * 4 * self.width as usize;
+        let chunk_size = 4 + 4 + line_size;
+        let table_end = header.len() + 8 * self.height as usize;
+        for y in 0..self.height as usize {
+            w.write_all(&((table_end + y * chunk_size) as u64).to_le_bytes())?;
+        }
+
+        let data = self.to_f32();
+        for y in 0..self.height as usize {
+            w.write_all(&(y as i32).to_le_bytes())?;
+            w.write_all(&(line_size as i32).to_le_bytes())?;
+            let row = &data[y * self.width as usize..(y + 1) * self.width as usize];
+            for c in (0..3).rev() {
+                for p in row {
+                    w.write_all(&p[c].to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Filtered samples of one render tile. It covers the tile plus the margin
+// the filter reaches into, so neighbouring tiles overlap and are summed by
+// Framebuffer::merge_tile.
+pub struct FilmTile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+    filter: Filter,
+    sum: Vec<Color>,
+    weight: Vec<f64>,
+}
+
+impl FilmTile {
+    // Bounds are those of the pixels whose samples go in, in a film of the
+    // given size.
+    pub fn new(
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+        width: u32,
+        height: u32,
+        filter: Filter,
+    ) -> FilmTile {
+        let margin = (filter.radius - 0.5).max(0.0).ceil() as u32;
+        let x0 = x0.saturating_sub(margin);
+        let y0 = y0.saturating_sub(margin);
+        let x1 = (x1 + margin).min(width);
+        let y1 = (y1 + margin).min(height);
+        let size = ((x1 - x0) * (y1 - y0)) as usize;
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            filter,
+            sum: vec![Color::default(); size],
+            weight: vec![0.0; size],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0) * (self.x1 - self.x0) + x - self.x0) as usize
+    }
+
+    // Splats a sample at continuous film position (fx, fy), where pixel
+    // (x, y) covers [x, x+1) x [y, y+1), into every pixel the filter reaches.
+    pub fn add_sample(&mut self, fx: f64, fy: f64, color: Color) {
+        let r = self.filter.radius;
+        let lo = |f: f64, min: u32| ((f - 0.5 - r).floor() as i64).max(min as i64) as u32;
+        let hi = |f: f64, max: u32| ((f - 0.5 + r).floor() as i64 + 1).min(max as i64) as u32;
+        for y in lo(fy, self.y0)..hi(fy, self.y1) {
+            for x in lo(fx, self.x0)..hi(fx, self.x1) {
+                let w = self
+                    .filter
+                    .evaluate(fx - (x as f64 + 0.5), fy - (y as f64 + 0.5));
+                if w != 0.0 {
+                    let i = self.index(x, y);
+                    self.sum[i] += w * color;
+                    self.weight[i] += w;
+                }
+            }
+        }
+    }
+}
+
+fn add_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
@@ -1,7 +1,5 @@
-use std::f64::INFINITY;
-use std::f64::NEG_INFINITY;
-
 use crate::aabb::*;
+use crate::mat4::*;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::rtweekend::*;
@@ -43,17 +41,47 @@ impl Default for HitRecord {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> Option<&dyn Material>;
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material>;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool;
 
-    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f64 {
+    fn pdf_value(&self, _o: Point3, _v: Vec3, _rng: &mut RandGen) -> f64 {
         0.0
     }
-    fn random(&self, _o: Vec3) -> Vec3 {
+    fn random(&self, _o: Vec3, _u: (f64, f64)) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
 }
 
+impl<H: Hittable + ?Sized> Hittable for Box<H> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        (**self).hit(r, t_min, t_max, rec, rng)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        (**self).bounding_box(time0, time1, output_box)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        (**self).pdf_value(o, v, rng)
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        (**self).random(o, u)
+    }
+}
+
 pub struct Translate<H: Hittable> {
     ptr: H,
     offset: Vec3,
@@ -76,15 +104,29 @@ impl<H: Hittable> Hittable for Translate<H> {
 
         true
     }
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> Option<&dyn Material> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
         let moved_r = Ray::new(r.origin() - self.offset, r.direction(), r.time());
-        if let Some(opt) = self.ptr.hit(&moved_r, t_min, t_max, rec) {
+        if let Some(opt) = self.ptr.hit(&moved_r, t_min, t_max, rec, rng) {
             rec.p += self.offset;
             rec.set_face_normal(&moved_r, rec.normal);
             return Some(opt);
         }
         None
     }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        self.ptr.pdf_value(o - self.offset, v, rng)
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        self.ptr.random(o - self.offset, u)
+    }
 }
 
 pub struct RotateY<H: Hittable> {
@@ -102,8 +144,8 @@ impl<H: Hittable> RotateY<H> {
         let mut bbox = Aabb::default();
         let hasbox = ptr.bounding_box(0.0, 1.0, &mut bbox);
 
-        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
-        let mut max = Point3::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY);
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
 
         for i in 0..2 {
             for j in 0..2 {
@@ -136,12 +178,35 @@ impl<H: Hittable> RotateY<H> {
         }
     }
 }
+impl<H: Hittable> RotateY<H> {
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v[0] - self.sin_theta * v[2],
+            v[1],
+            self.sin_theta * v[0] + self.cos_theta * v[2],
+        )
+    }
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v[0] + self.sin_theta * v[2],
+            v[1],
+            -self.sin_theta * v[0] + self.cos_theta * v[2],
+        )
+    }
+}
 impl<H: Hittable> Hittable for RotateY<H> {
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         *output_box = self.bbox;
         self.hasbox
     }
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> Option<&dyn Material> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
         let mut origin = r.origin();
         let mut direction = r.direction();
 
@@ -153,7 +218,7 @@ impl<H: Hittable> Hittable for RotateY<H> {
 
         let rotated_r = Ray::new(origin, direction, r.time());
 
-        if let Some(opt) = self.ptr.hit(&rotated_r, t_min, t_max, rec) {
+        if let Some(opt) = self.ptr.hit(&rotated_r, t_min, t_max, rec, rng) {
             let mut p = rec.p;
             let mut normal = rec.normal;
 
@@ -170,6 +235,15 @@ impl<H: Hittable> Hittable for RotateY<H> {
         }
         None
     }
+
+    // Rotations preserve solid angle, so the pdf needs no correction.
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        self.ptr
+            .pdf_value(self.to_object(o), self.to_object(v), rng)
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        self.to_world(self.ptr.random(self.to_object(o), u))
+    }
 }
 
 pub struct FlipFace<H: Hittable> {
@@ -181,8 +255,15 @@ impl<H: Hittable> FlipFace<H> {
     }
 }
 impl<H: Hittable> Hittable for FlipFace<H> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> Option<&dyn Material> {
-        match self.ptr.hit(r, t_min, t_max, rec) {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        match self.ptr.hit(r, t_min, t_max, rec, rng) {
             Some(ptr) => {
                 rec.front_face = !rec.front_face;
                 Some(ptr)
@@ -193,4 +274,162 @@ impl<H: Hittable> Hittable for FlipFace<H> {
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
         self.ptr.bounding_box(time0, time1, output_box)
     }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        self.ptr.pdf_value(o, v, rng)
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        self.ptr.random(o, u)
+    }
+}
+
+// Instance of an object under any affine transform, applied in one step.
+pub struct Transform<H: Hittable> {
+    ptr: H,
+    m: Mat4,
+    inv: Mat4,
+    // Inverse transpose, which keeps normals perpendicular to the surface.
+    normal_m: Mat4,
+    hasbox: bool,
+    bbox: Aabb,
+}
+impl<H: Hittable> Transform<H> {
+    // None if the matrix can't be inverted.
+    pub fn new(ptr: H, m: Mat4) -> Option<Transform<H>> {
+        let inv = m.inverse()?;
+        let mut bbox = Aabb::default();
+        let hasbox = ptr.bounding_box(0.0, 1.0, &mut bbox);
+
+        // An affine map sends the box to a parallelepiped, which the
+        // transformed corners bound exactly.
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let x = if i == 1 {
+                        bbox.max().x()
+                    } else {
+                        bbox.min().x()
+                    };
+                    let y = if j == 1 {
+                        bbox.max().y()
+                    } else {
+                        bbox.min().y()
+                    };
+                    let z = if k == 1 {
+                        bbox.max().z()
+                    } else {
+                        bbox.min().z()
+                    };
+                    let tester = m.transform_point(Point3::new(x, y, z));
+                    for c in 0..3 {
+                        min[c] = min[c].min(tester[c]);
+                        max[c] = max[c].max(tester[c]);
+                    }
+                }
+            }
+        }
+
+        Some(Transform {
+            ptr,
+            m,
+            inv,
+            normal_m: inv.transpose(),
+            hasbox,
+            bbox: Aabb::new(min, max),
+        })
+    }
+    pub fn matrix(&self) -> Mat4 {
+        self.m
+    }
+}
+impl<H: Hittable> Hittable for Transform<H> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        // The direction is not renormalized, so t is the same in both spaces.
+        let local_r = Ray::new(
+            self.inv.transform_point(r.origin()),
+            self.inv.transform_vector(r.direction()),
+            r.time(),
+        );
+        let opt = self.ptr.hit(&local_r, t_min, t_max, rec, rng)?;
+        rec.p = self.m.transform_point(rec.p);
+        // rec.normal already faces the ray, and the inverse transpose keeps
+        // its side, so front_face carries over.
+        rec.normal = unit_vector(self.normal_m.transform_vector(rec.normal));
+        Some(opt)
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = self.bbox;
+        self.hasbox
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        let local_v = self.inv.transform_vector(v);
+        let pdf = self
+            .ptr
+            .pdf_value(self.inv.transform_point(o), local_v, rng);
+        // Solid angle changes by |det A| / |A w|^3 for the linear part A of
+        // the inverse and the unit world direction w.
+        let stretch = local_v.length() / v.length();
+        pdf * self.inv.determinant3().abs() / (stretch * stretch * stretch)
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        // Affine maps keep differences of points, so the local offset to the
+        // sampled point maps straight to the world one.
+        self.m
+            .transform_vector(self.ptr.random(self.inv.transform_point(o), u))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::*;
+    use crate::material::*;
+    use crate::sphere::*;
+
+    #[test]
+    fn normals_follow_a_non_uniform_scale() {
+        // An ellipsoid with semi-axes 2, 1 and 1.
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Vec3::new(0.5, 0.5, 0.5)),
+        );
+        let ellipsoid = Transform::new(sphere, Mat4::scale(Vec3::new(2.0, 1.0, 1.0))).unwrap();
+
+        let p = Point3::new(2.0f64.sqrt(), 0.5f64.sqrt(), 0.0);
+        let n = unit_vector(Vec3::new(p.x() / 4.0, p.y(), p.z()));
+        let r = Ray::new(p + 3.0 * n, -n, 0.0);
+        let mut rec = HitRecord::default();
+        let rng = &mut sample_rng(0, 0, 0);
+        assert!(ellipsoid
+            .hit(&r, 0.001, f64::INFINITY, &mut rec, rng)
+            .is_some());
+        assert!((rec.p - p).length() < 1e-9);
+        assert!((unit_vector(rec.normal) - n).length() < 1e-9);
+    }
+
+    #[test]
+    fn rotated_box_is_bounded_by_its_corners() {
+        let cube = Cube::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            Lambertian::new(Vec3::new(0.5, 0.5, 0.5)),
+        );
+        let rotated = Transform::new(cube, Mat4::rotate_z(45.0)).unwrap();
+        let mut bbox = Aabb::default();
+        assert!(rotated.bounding_box(0.0, 1.0, &mut bbox));
+        let h = 0.5f64.sqrt();
+        assert!((bbox.min() - Point3::new(-h, 0.0, 0.0)).length() < 1e-9);
+        assert!((bbox.max() - Point3::new(h, 2.0 * h, 1.0)).length() < 1e-9);
+    }
 }
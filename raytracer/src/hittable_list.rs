@@ -25,13 +25,20 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> Option<&dyn Material> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
         let mut temp_rec: HitRecord = HitRecord::default();
         let mut hit_anything: Option<&dyn Material> = None;
         let mut closest_so_far = t_max;
 
         for object in &self.objects {
-            if let Some(opt) = object.hit(r, t_min, closest_so_far, &mut temp_rec) {
+            if let Some(opt) = object.hit(r, t_min, closest_so_far, &mut temp_rec, rng) {
                 hit_anything = Some(opt);
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec;
@@ -66,17 +73,24 @@ impl Hittable for HittableList {
         true
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
         let weight = 1.0 / (self.objects.len() as f64);
         let mut sum = 0.0;
 
         for object in &self.objects {
-            sum += weight * object.pdf_value(o, v);
+            sum += weight * object.pdf_value(o, v, rng);
         }
         sum
     }
-    fn random(&self, o: crate::vec3::Vec3) -> Vec3 {
-        let int_size = self.objects.len() as i32;
-        self.objects[random_int(0, int_size - 1) as usize].random(o)
+    fn random(&self, o: crate::vec3::Vec3, u: (f64, f64)) -> Vec3 {
+        // Pick an object with the first coordinate and reuse what is left of it.
+        let n = self.objects.len();
+        if n == 0 {
+            // Any direction will do, pdf_value is zero for all of them.
+            return Vec3::new(0.0, 1.0, 0.0);
+        }
+        let scaled = u.0 * n as f64;
+        let i = (scaled as usize).min(n - 1);
+        self.objects[i].random(o, ((scaled - i as f64).min(1.0), u.1))
     }
 }
@@ -0,0 +1,218 @@
+use std::io;
+use std::path::Path;
+
+use crate::rtweekend::*;
+
+// A luminaire's candela distribution from an IES LM-63 photometric file,
+// scaled so its brightest direction is 1. Vertical angles are measured from
+// the light's axis, horizontal ones around it, both in degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    vertical: Vec<f64>,
+    horizontal: Vec<f64>,
+    // One row of vertical samples per horizontal angle.
+    candela: Vec<f64>,
+}
+
+impl IesProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<IesProfile> {
+        let bytes = std::fs::read(path)?;
+        IesProfile::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    pub fn parse(text: &str) -> io::Result<IesProfile> {
+        // Keywords come first, up to the TILT line.
+        let mut lines = text.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => {
+                    break line.trim_start()["TILT=".len()..].trim().to_string()
+                }
+                Some(_) => {}
+                None => return Err(invalid_data("missing TILT line")),
+            }
+        };
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<f64>()
+                    .map_err(|_| invalid_data("malformed number"))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(invalid_data("file ends early")))
+        };
+
+        match tilt.as_str() {
+            "NONE" => {}
+            "INCLUDE" => {
+                // Only matters for lamps tilted away from how they were
+                // measured, so it is skipped.
+                next()?;
+                let pairs = next()? as usize;
+                for _ in 0..2 * pairs {
+                    next()?;
+                }
+            }
+            _ => {
+                return Err(invalid_data(
+                    "tilt data in a separate file is not supported",
+                ))
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        for _ in 0..7 {
+            // Units, luminous dimensions, ballast factors and watts.
+            next()?;
+        }
+        if photometric_type != 1.0 {
+            return Err(invalid_data("only type C photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid_data("no candela values"));
+        }
+
+        let mut read = |n: usize| (0..n).map(|_| next()).collect::<io::Result<Vec<f64>>>();
+        let vertical = read(vertical_count)?;
+        let horizontal = read(horizontal_count)?;
+        let count = vertical_count
+            .checked_mul(horizontal_count)
+            .ok_or_else(|| invalid_data("too many candela values"))?;
+        let mut candela = read(count)?;
+        let increasing = |angles: &[f64]| angles.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&vertical) || !increasing(&horizontal) {
+            return Err(invalid_data("angles must be increasing"));
+        }
+        // Type C files start at 0, or at 90 when they only hold the half of
+        // the light from 90 to 270.
+        let lateral = horizontal[0] == 90.0 && horizontal[horizontal_count - 1] == 270.0;
+        if horizontal[0] != 0.0 && !lateral {
+            return Err(invalid_data("unsupported horizontal angles"));
+        }
+
+        let max = candela.iter().map(|c| c * multiplier).fold(0.0, f64::max);
+        if max <= 0.0 {
+            return Err(invalid_data("the light is dark in every direction"));
+        }
+        for c in candela.iter_mut() {
+            *c = (*c * multiplier).max(0.0) / max;
+        }
+        Ok(IesProfile {
+            vertical,
+            horizontal,
+            candela,
+        })
+    }
+
+    // Relative intensity toward the given vertical and horizontal angles.
+    pub fn value(&self, vertical: f64, horizontal: f64) -> f64 {
+        let n = self.vertical.len();
+        let (v, tv) = match bracket(&self.vertical, vertical) {
+            Some(b) => b,
+            None => return 0.0,
+        };
+
+        // Files store as little of the distribution as its symmetry allows.
+        let mut h = horizontal.rem_euclid(360.0);
+        let last = self.horizontal[self.horizontal.len() - 1];
+        if self.horizontal[0] == 90.0 {
+            // Mirrored across the plane through 90 and 270.
+            if h < 90.0 {
+                h = 180.0 - h;
+            } else if h > 270.0 {
+                h = 540.0 - h;
+            }
+        } else if last <= 90.0 {
+            h %= 180.0;
+            if h > 90.0 {
+                h = 180.0 - h;
+            }
+        } else if last <= 180.0 && h > 180.0 {
+            h = 360.0 - h;
+        }
+        let (row, th) = if self.horizontal.len() == 1 {
+            (0, 0.0)
+        } else {
+            bracket(&self.horizontal, h).unwrap_or((self.horizontal.len() - 2, 1.0))
+        };
+
+        let at = |row: usize, col: usize| {
+            let row = row.min(self.horizontal.len() - 1);
+            let col = col.min(n - 1);
+            self.candela[row * n + col]
+        };
+        let lerp = |row: usize| (1.0 - tv) * at(row, v) + tv * at(row, v + 1);
+        (1.0 - th) * lerp(row) + th * lerp(row + 1)
+    }
+}
+
+// The interval of sorted angles x falls in, with how far along it is.
+fn bracket(angles: &[f64], x: f64) -> Option<(usize, f64)> {
+    let n = angles.len();
+    if x < angles[0] || x > angles[n - 1] {
+        return None;
+    }
+    if n == 1 {
+        return Some((0, 0.0));
+    }
+    let i = (angles.partition_point(|&a| a <= x).max(1) - 1).min(n - 2);
+    let t = (x - angles[i]) / (angles[i + 1] - angles[i]);
+    Some((i, t.clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(counts: &str, horizontal: &str, candela: &str) -> io::Result<IesProfile> {
+        IesProfile::parse(&format!(
+            "IESNA:LM-63-2002\n[TEST] test\nTILT=NONE\n1 1000 1 {} 1 1 0 0 0\n1 1 100\n0 90\n{}\n{}\n",
+            counts, horizontal, candela
+        ))
+    }
+
+    #[test]
+    fn mirrors_a_lateral_half_across_90_and_270() {
+        let ies = profile("2 3", "90 180 270", "100 50\n200 100\n300 150").unwrap();
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+
+        assert!(close(ies.value(0.0, 90.0), 1.0 / 3.0));
+        assert!(close(ies.value(90.0, 270.0), 0.5));
+        assert!(close(ies.value(45.0, 180.0), 0.5));
+        // Angles outside the stored half read their mirror image.
+        assert!(close(ies.value(0.0, 0.0), 2.0 / 3.0));
+        assert!(close(ies.value(0.0, 45.0), 0.5));
+        assert!(close(ies.value(0.0, 315.0), 5.0 / 6.0));
+        assert!(close(ies.value(0.0, -45.0), 5.0 / 6.0));
+    }
+
+    #[test]
+    fn mirrors_a_quadrant() {
+        let ies = profile("2 2", "0 90", "100 100\n200 200").unwrap();
+        assert_eq!(ies.value(0.0, 0.0), 0.5);
+        assert_eq!(ies.value(0.0, 90.0), 1.0);
+        assert_eq!(ies.value(0.0, 180.0), 0.5);
+        assert_eq!(ies.value(0.0, 270.0), 1.0);
+        assert_eq!(ies.value(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        assert!(profile("2 2", "45 90", "100 100\n200 200").is_err());
+        assert!(profile("2 2", "90 0", "100 100\n200 200").is_err());
+        assert!(profile("2 2", "0 90", "100 100\n200").is_err());
+        assert!(profile("2 2", "0 90", "0 0\n0 0").is_err());
+        assert!(profile("4294967296 4294967296", "0", "1").is_err());
+        assert!(IesProfile::parse("1 1000 1 1 1 1 1 0 0 0\n").is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::environment::*;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::light::*;
+use crate::light_sampler::*;
+use crate::material::*;
+use crate::pdf::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::sampler::*;
+use crate::vec3::*;
+
+// How the light sample and the bounce share the credit for emission that both
+// of them can find.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    Power,
+}
+
+impl FromStr for MisHeuristic {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balance" => Ok(MisHeuristic::Balance),
+            "power" => Ok(MisHeuristic::Power),
+            _ => Err(format!(
+                "unknown MIS heuristic \"{}\", expected balance or power",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MisHeuristic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MisHeuristic::Balance => "balance",
+            MisHeuristic::Power => "power",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl MisHeuristic {
+    // Weight of a sample drawn with density `pdf` when `other` could have
+    // drawn it too.
+    pub fn weight(&self, pdf: f64, other: f64) -> f64 {
+        let (f, g) = match self {
+            MisHeuristic::Balance => (pdf, other),
+            MisHeuristic::Power => (pdf * pdf, other * other),
+        };
+        if f + g > 0.0 {
+            f / (f + g)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Totals behind the render statistics.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PathStats {
+    pub paths: u64,
+    // Surfaces and media hit along them.
+    pub vertices: u64,
+}
+
+impl PathStats {
+    pub fn merge(&mut self, other: &PathStats) {
+        self.paths += other.paths;
+        self.vertices += other.vertices;
+    }
+
+    pub fn average_length(&self) -> f64 {
+        if self.paths == 0 {
+            0.0
+        } else {
+            self.vertices as f64 / self.paths as f64
+        }
+    }
+}
+
+// Bounces are limited separately by kind. A bounce that crosses the surface
+// is a transmission, whether or not it was sampled from a pdf.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Bounce {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+fn is_black(c: Color) -> bool {
+    c.x() == 0.0 && c.y() == 0.0 && c.z() == 0.0
+}
+
+// At every vertex that isn't a perfect mirror or refraction, the path tracer
+// samples the lights with a shadow ray before bouncing off the material.
+// Emission that both the shadow ray and the bounce can find is weighted by MIS.
+pub struct PathTracer<'a> {
+    pub world: &'a dyn Hittable,
+    pub lights: &'a HittableList,
+    pub punctual: &'a [PunctualLight],
+    pub background: &'a Background,
+    pub light_sampler: &'a LightSampler,
+    pub max_depth: i32,
+    // Paths shorter than this are never cut short by Russian roulette.
+    pub roulette_depth: i32,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    pub mis: MisHeuristic,
+}
+
+impl<'a> PathTracer<'a> {
+    pub fn ray_color(
+        &self,
+        r: &Ray,
+        sampler: &mut dyn Sampler,
+        rng: &mut RandGen,
+        stats: &mut PathStats,
+    ) -> Color {
+        let has_lights = !self.light_sampler.is_empty();
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        // Density of the bounce that found this ray, or None if the emission
+        // it finds can't have been light sampled.
+        let mut bounce_pdf: Option<f64> = None;
+        let mut origin = ray.origin();
+        let mut bounces = [0; 3];
+        stats.paths += 1;
+
+        // If we've exceeded the ray bounce limit, no more light is gathered.
+        for depth in 0..self.max_depth {
+            // Unbiased, since survivors make up for the paths that stop.
+            if depth >= self.roulette_depth {
+                let survival = throughput.x().max(throughput.y()).max(throughput.z());
+                if survival < 1.0 {
+                    if random_double(rng) >= survival {
+                        break;
+                    }
+                    throughput = throughput / survival;
+                }
+            }
+
+            let mut rec = HitRecord::default();
+
+            // If the ray hits nothing, return the background color.
+            let mat_ptr = match self.world.hit(&ray, 0.001, f64::INFINITY, &mut rec, rng) {
+                Some(mat_ptr) => mat_ptr,
+                None => {
+                    if let Background::Color(background) = self.background {
+                        color += throughput * *background;
+                    }
+                    let sky = self.sky(ray.direction());
+                    if !is_black(sky) {
+                        let weight = match bounce_pdf {
+                            Some(pdf) => {
+                                let light_pdf = self.light_pdf(origin, ray.direction(), rng);
+                                self.mis.weight(pdf, light_pdf)
+                            }
+                            None => 1.0,
+                        };
+                        color += weight * throughput * sky;
+                    }
+                    break;
+                }
+            };
+            stats.vertices += 1;
+
+            // Drawn whether or not the vertex needs them, so every path uses
+            // the same sampler dimensions at the same depth.
+            let u_light = sampler.get_2d();
+            let u_bounce = sampler.get_2d();
+
+            let emitted = mat_ptr.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+            if !is_black(emitted) {
+                let weight = match bounce_pdf {
+                    Some(pdf) => {
+                        let light_pdf = self.light_pdf(origin, ray.direction(), rng);
+                        self.mis.weight(pdf, light_pdf)
+                    }
+                    None => 1.0,
+                };
+                color += weight * throughput * emitted;
+            }
+
+            let mut srec = ScatterRecord::default();
+            let (scattered, pdf_ptr) = mat_ptr.scatter(&ray, &rec, &mut srec, rng);
+            if !scattered {
+                break;
+            }
+
+            let pdf_ptr = match pdf_ptr {
+                Some(pdf_ptr) => pdf_ptr,
+                None => {
+                    let kind = if dot(srec.specular_ray.direction(), rec.normal) < 0.0 {
+                        Bounce::Transmission
+                    } else {
+                        Bounce::Specular
+                    };
+                    if !self.bounce(&mut bounces, kind) {
+                        break;
+                    }
+                    throughput = throughput * srec.attenuation;
+                    ray = srec.specular_ray;
+                    bounce_pdf = None;
+                    continue;
+                }
+            };
+
+            if has_lights {
+                color += throughput
+                    * self.sample_light(&ray, &rec, mat_ptr, &srec, &*pdf_ptr, u_light, rng);
+            }
+
+            let scattered = Ray::new(rec.p, pdf_ptr.generate(u_bounce), ray.time());
+            let kind = if dot(scattered.direction(), rec.normal) < 0.0 {
+                Bounce::Transmission
+            } else {
+                Bounce::Diffuse
+            };
+            if !self.bounce(&mut bounces, kind) {
+                break;
+            }
+            let pdf_val = pdf_ptr.value(scattered.direction(), rng);
+            if pdf_val <= 0.0 {
+                break;
+            }
+            let f = mat_ptr.scattering(&ray, &rec, &srec, &scattered);
+            if is_black(f) {
+                break;
+            }
+
+            throughput = throughput * f / pdf_val;
+            bounce_pdf = Some(pdf_val);
+            origin = rec.p;
+            ray = scattered;
+        }
+
+        color
+    }
+
+    // Counts a bounce, or returns false if there can be no more of its kind.
+    fn bounce(&self, bounces: &mut [i32; 3], kind: Bounce) -> bool {
+        let limit = match kind {
+            Bounce::Diffuse => self.diffuse_depth,
+            Bounce::Specular => self.specular_depth,
+            Bounce::Transmission => self.transmission_depth,
+        };
+        let count = &mut bounces[kind as usize];
+        if let Some(limit) = limit {
+            if *count >= limit {
+                return false;
+            }
+        }
+        *count += 1;
+        true
+    }
+
+    // What samples directions toward a light that rays can hit.
+    fn light_pdf_of(&self, light: LightRef, o: Point3) -> Option<Box<dyn Pdf + 'a>> {
+        match light {
+            LightRef::Shape(j) => Some(Box::new(HittablePdf::new(&*self.lights.objects[j], o))),
+            LightRef::Environment => self
+                .background
+                .environment()
+                .map(|map| Box::new(EnvironmentPdf::new(map)) as Box<dyn Pdf + 'a>),
+            LightRef::Punctual(j) if !self.punctual[j].is_delta() => {
+                Some(Box::new(DistantPdf::new(&self.punctual[j])))
+            }
+            LightRef::Punctual(_) => None,
+        }
+    }
+
+    // What an escaping ray sees that shadow rays can find too: the
+    // environment map and the disks of distant lights.
+    fn sky(&self, direction: Vec3) -> Color {
+        let mut radiance = match self.background.environment() {
+            Some(map) => map.radiance(direction),
+            None => Color::new(0.0, 0.0, 0.0),
+        };
+        for light in self.punctual {
+            if !light.is_delta() {
+                radiance += light.radiance(direction);
+            }
+        }
+        radiance
+    }
+
+    // Density of shadow ray directions from o, over all the lights that
+    // could have been picked. Shapes whose bounds the direction misses are
+    // skipped, and the chance of picking a light is only worked out for the
+    // ones that can send a ray along it.
+    fn light_pdf(&self, o: Point3, direction: Vec3, rng: &mut RandGen) -> f64 {
+        let r = Ray::new(o, direction, 0.0);
+        let mut pdf = 0.0;
+        for (i, light) in self.light_sampler.lights().iter().enumerate() {
+            let value = match *light {
+                LightRef::Shape(j) => {
+                    if let Some(bounds) = self.light_sampler.bounds(i) {
+                        if !bounds.hit(&r, 0.001, f64::INFINITY) {
+                            continue;
+                        }
+                    }
+                    self.lights.objects[j].pdf_value(o, direction, rng)
+                }
+                LightRef::Environment => match self.background.environment() {
+                    Some(map) => map.pdf(direction),
+                    None => continue,
+                },
+                LightRef::Punctual(j) if !self.punctual[j].is_delta() => {
+                    DistantPdf::new(&self.punctual[j]).value(direction, rng)
+                }
+                LightRef::Punctual(_) => continue,
+            };
+            if value > 0.0 {
+                pdf += self.light_sampler.chance(o, i) * value;
+            }
+        }
+        pdf
+    }
+
+    // Next-event estimation: emission reaching rec.p along one shadow ray
+    // toward the lights, weighted against the bounce density `bounce`. Light
+    // from a delta light can't be found by bouncing, so it isn't weighted.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_light(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        mat_ptr: &dyn Material,
+        srec: &ScatterRecord,
+        bounce: &dyn Pdf,
+        u: (f64, f64),
+        rng: &mut RandGen,
+    ) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+
+        // The first coordinate picks the light and is then reused.
+        let (i, chance, u0) = match self.light_sampler.pick(rec.p, u.0) {
+            Some(picked) => picked,
+            None => return black,
+        };
+        let u = (u0, u.1);
+        let light = self.light_sampler.lights()[i];
+        let delta = match light {
+            LightRef::Punctual(j) if self.punctual[j].is_delta() => Some(j),
+            _ => None,
+        };
+        if let Some(j) = delta {
+            let sample = match self.punctual[j].sample_li(rec.p, u) {
+                Some(sample) => sample,
+                None => return black,
+            };
+            let shadow = Ray::new(rec.p, sample.direction, r_in.time());
+            let f = mat_ptr.scattering(r_in, rec, srec, &shadow);
+            if is_black(f) {
+                return black;
+            }
+            let mut blocker = HitRecord::default();
+            if self
+                .world
+                .hit(&shadow, 0.001, sample.distance, &mut blocker, rng)
+                .is_some()
+            {
+                return black;
+            }
+            return f * sample.li / chance;
+        }
+
+        let direction = match self.light_pdf_of(light, rec.p) {
+            Some(light_pdf) => light_pdf.generate(u),
+            None => return black,
+        };
+        let light_pdf = self.light_pdf(rec.p, direction, rng);
+        if light_pdf <= 0.0 {
+            return black;
+        }
+        let shadow = Ray::new(rec.p, direction, r_in.time());
+        let f = mat_ptr.scattering(r_in, rec, srec, &shadow);
+        if is_black(f) {
+            return black;
+        }
+
+        let mut light_rec = HitRecord::default();
+        let emitted = match self
+            .world
+            .hit(&shadow, 0.001, f64::INFINITY, &mut light_rec, rng)
+        {
+            Some(light_mat) => {
+                light_mat.emitted(&shadow, &light_rec, light_rec.u, light_rec.v, light_rec.p)
+            }
+            None => self.sky(direction),
+        };
+        if is_black(emitted) {
+            return black;
+        }
+
+        let weight = self.mis.weight(light_pdf, bounce.value(direction, rng));
+        weight * f * emitted / light_pdf
+    }
+}
@@ -0,0 +1,65 @@
+pub mod aabb;
+pub mod aarect;
+pub mod bvh;
+pub mod camera;
+pub mod canny;
+pub mod checkpoint;
+pub mod color;
+pub mod constant_medium;
+pub mod cube;
+pub mod distribution;
+pub mod environment;
+pub mod filter;
+pub mod framebuffer;
+pub mod hittable;
+pub mod hittable_list;
+pub mod ies;
+pub mod integrator;
+pub mod light;
+pub mod light_sampler;
+pub mod mat4;
+pub mod material;
+pub mod microfacet;
+pub mod moving_shpere;
+pub mod obj;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
+pub mod ray;
+pub mod renderer;
+pub mod rtweekend;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod sky;
+pub mod sphere;
+pub mod texture;
+pub mod tonemap;
+pub mod triangle;
+pub mod vec3;
+
+pub use bvh::BvhNode;
+pub use camera::Camera;
+pub use checkpoint::Checkpoint;
+pub use environment::{Background, EnvironmentMap};
+pub use filter::{Filter, FilterKind};
+pub use framebuffer::Framebuffer;
+pub use hittable::{Hittable, Transform};
+pub use hittable_list::HittableList;
+pub use ies::IesProfile;
+pub use integrator::{MisHeuristic, PathTracer};
+pub use light::PunctualLight;
+pub use light_sampler::LightSampling;
+pub use mat4::Mat4;
+pub use material::{DiffuseLight, EmissionProfile, Material, Principled};
+pub use obj::load_obj;
+pub use pdf::Pdf;
+pub use ray::Ray;
+pub use renderer::{RenderState, Renderer};
+pub use sampler::{Sampler, SamplerKind};
+pub use scene::Scene;
+pub use sky::Sky;
+pub use texture::Texture;
+pub use tonemap::ToneMapper;
+pub use triangle::{Triangle, TriangleMesh};
+pub use vec3::{Color, Point3, Vec3};
@@ -0,0 +1,216 @@
+use std::f64::consts::PI;
+
+use crate::onb::*;
+use crate::pdf::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// Lights without a surface. Apart from the disk of a distant light with an
+// angular diameter, no ray can hit them, so they are only ever found by shadow
+// rays.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PunctualLight {
+    Point {
+        position: Point3,
+        // Power per unit solid angle.
+        intensity: Color,
+    },
+    Spot {
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        // Full intensity inside the inner cone, none outside the outer one.
+        cos_inner: f64,
+        cos_outer: f64,
+    },
+    // Infinitely far away, like the sun. With an angular diameter it is a
+    // disk of uniform radiance in the sky, and casts soft shadows.
+    Distant {
+        // Toward the light.
+        direction: Vec3,
+        // Power per unit area facing the light.
+        irradiance: Color,
+        cos_max: f64,
+    },
+}
+
+// What a shadow ray toward a punctual light needs to know.
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f64,
+    // Incident radiance, already divided by the density of direction.
+    pub li: Color,
+}
+
+impl PunctualLight {
+    pub fn point(position: Point3, intensity: Color) -> PunctualLight {
+        PunctualLight::Point {
+            position,
+            intensity,
+        }
+    }
+
+    // The cone's half-angle and the band inside its edge where the light
+    // fades out, in degrees.
+    pub fn spot(
+        position: Point3,
+        target: Point3,
+        intensity: Color,
+        cone_angle: f64,
+        falloff_angle: f64,
+    ) -> PunctualLight {
+        let cos_outer = degrees_to_radians(cone_angle).cos();
+        let cos_inner = degrees_to_radians((cone_angle - falloff_angle).max(0.0)).cos();
+        PunctualLight::Spot {
+            position,
+            direction: unit_vector(target - position),
+            intensity,
+            cos_inner,
+            cos_outer,
+        }
+    }
+
+    pub fn distant(direction: Vec3, irradiance: Color, angular_diameter: f64) -> PunctualLight {
+        PunctualLight::Distant {
+            direction: unit_vector(direction),
+            irradiance,
+            cos_max: degrees_to_radians(angular_diameter / 2.0).cos(),
+        }
+    }
+
+    // None for distant lights, which have no place in the scene.
+    pub fn position(&self) -> Option<Point3> {
+        match *self {
+            PunctualLight::Point { position, .. } | PunctualLight::Spot { position, .. } => {
+                Some(position)
+            }
+            PunctualLight::Distant { .. } => None,
+        }
+    }
+
+    // Lights that only one direction reaches a point from, which nothing but
+    // a shadow ray can find.
+    pub fn is_delta(&self) -> bool {
+        match *self {
+            PunctualLight::Distant { cos_max, .. } => cos_max >= 1.0,
+            _ => true,
+        }
+    }
+
+    // Radiance seen looking along direction, which only a distant light's disk
+    // has.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        match *self {
+            PunctualLight::Distant {
+                direction: to_light,
+                irradiance,
+                cos_max,
+            } if cos_max < 1.0 && dot(unit_vector(direction), to_light) >= cos_max => {
+                irradiance / (2.0 * PI * (1.0 - cos_max))
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Density of the directions sample_li draws, zero for delta lights.
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match *self {
+            PunctualLight::Distant {
+                direction: to_light,
+                cos_max,
+                ..
+            } if cos_max < 1.0 && dot(unit_vector(direction), to_light) >= cos_max => {
+                1.0 / (2.0 * PI * (1.0 - cos_max))
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn sample_li(&self, p: Point3, u: (f64, f64)) -> Option<LightSample> {
+        match *self {
+            PunctualLight::Point {
+                position,
+                intensity,
+            } => toward(p, position, intensity),
+            PunctualLight::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let cos_theta = dot(unit_vector(p - position), direction);
+                let falloff = smoothstep(cos_outer, cos_inner, cos_theta);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                toward(p, position, falloff * intensity)
+            }
+            PunctualLight::Distant {
+                direction,
+                irradiance,
+                cos_max,
+            } => {
+                let direction = if cos_max < 1.0 {
+                    // Uniform over the disk's cone.
+                    let z = 1.0 - u.0 * (1.0 - cos_max);
+                    let r = (1.0 - z * z).max(0.0).sqrt();
+                    let phi = 2.0 * PI * u.1;
+                    let mut uvw = Onb::default();
+                    uvw.build_from_w(direction);
+                    uvw.local(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+                } else {
+                    direction
+                };
+                Some(LightSample {
+                    direction,
+                    distance: f64::INFINITY,
+                    li: irradiance,
+                })
+            }
+        }
+    }
+}
+
+fn toward(p: Point3, position: Point3, intensity: Color) -> Option<LightSample> {
+    let to_light = position - p;
+    let distance_squared = to_light.length_squared();
+    if distance_squared == 0.0 {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    Some(LightSample {
+        direction: to_light / distance,
+        distance,
+        li: intensity / distance_squared,
+    })
+}
+
+fn smoothstep(a: f64, b: f64, x: f64) -> f64 {
+    if a == b {
+        return if x < a { 0.0 } else { 1.0 };
+    }
+    let t = clamp((x - a) / (b - a), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Sampling of a distant light's disk, the same from wherever it is seen.
+pub struct DistantPdf<'a> {
+    light: &'a PunctualLight,
+}
+impl<'a> DistantPdf<'a> {
+    pub fn new(light: &'a PunctualLight) -> DistantPdf<'a> {
+        DistantPdf { light }
+    }
+}
+impl<'a> Pdf for DistantPdf<'a> {
+    fn value(&self, direction: Vec3, _rng: &mut RandGen) -> f64 {
+        self.light.pdf(direction)
+    }
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        match self.light.sample_li(Point3::new(0.0, 0.0, 0.0), u) {
+            Some(sample) => sample.direction,
+            None => Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+}
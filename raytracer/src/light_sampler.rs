@@ -0,0 +1,398 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::aabb::*;
+use crate::color::*;
+use crate::distribution::*;
+use crate::environment::*;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::light::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// How next-event estimation picks the light it sends a shadow ray to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSampling {
+    Uniform,
+    // In proportion to each light's power.
+    Power,
+    // Through a light tree, favouring bright lights close to the shading point.
+    Tree,
+}
+
+impl FromStr for LightSampling {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(LightSampling::Uniform),
+            "power" => Ok(LightSampling::Power),
+            "tree" => Ok(LightSampling::Tree),
+            _ => Err(format!(
+                "unknown light sampling \"{}\", expected uniform, power or tree",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LightSampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LightSampling::Uniform => "uniform",
+            LightSampling::Power => "power",
+            LightSampling::Tree => "tree",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A light of the scene: a shape in the lights list, the environment map or a
+// punctual light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightRef {
+    Shape(usize),
+    Environment,
+    Punctual(usize),
+}
+
+// Shading points the power of every light is estimated from.
+const PILOT_POINTS: usize = 64;
+
+// Picks lights for shadow rays. Built once per render.
+pub struct LightSampler {
+    lights: Vec<LightRef>,
+    // Bounds of the shape lights.
+    bounds: Vec<Option<Aabb>>,
+    // What the table picks from: a light, or the light tree as a whole.
+    entries: Vec<Option<usize>>,
+    entry_of: Vec<usize>,
+    table: AliasTable,
+    tree: Option<LightTree>,
+}
+
+impl LightSampler {
+    pub fn new(
+        mode: LightSampling,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        punctual: &[PunctualLight],
+        background: &Background,
+    ) -> LightSampler {
+        let mut refs: Vec<LightRef> = (0..lights.objects.len()).map(LightRef::Shape).collect();
+        if background.environment().is_some() {
+            refs.push(LightRef::Environment);
+        }
+        refs.extend((0..punctual.len()).map(LightRef::Punctual));
+
+        let power = match mode {
+            LightSampling::Uniform => vec![1.0; refs.len()],
+            _ => estimate_power(&refs, world, lights, punctual, background),
+        };
+
+        let shape_bounds: Vec<Option<Aabb>> = refs
+            .iter()
+            .map(|light| match light {
+                LightRef::Shape(j) => {
+                    let mut output_box = Aabb::default();
+                    if lights.objects[*j].bounding_box(0.0, 1.0, &mut output_box) {
+                        Some(output_box)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+
+        // Lights without a place in the scene stay out of the tree.
+        let mut bounded = Vec::new();
+        let mut entries = Vec::new();
+        for (i, light) in refs.iter().enumerate() {
+            let bounds = match (mode, light) {
+                (LightSampling::Tree, LightRef::Shape(_)) => shape_bounds[i],
+                (LightSampling::Tree, LightRef::Punctual(j)) => {
+                    punctual[*j].position().map(|p| Aabb::new(p, p))
+                }
+                _ => None,
+            };
+            match bounds {
+                Some(bounds) => bounded.push((i, bounds, power[i])),
+                None => entries.push(Some(i)),
+            }
+        }
+
+        let mut weights: Vec<f64> = entries.iter().flatten().map(|&i| power[i]).collect();
+        let mut entry_of = vec![0; refs.len()];
+        for (e, i) in entries.iter().enumerate() {
+            if let Some(i) = i {
+                entry_of[*i] = e;
+            }
+        }
+        let tree = if bounded.is_empty() {
+            None
+        } else {
+            weights.push(bounded.iter().map(|(_, _, power)| power).sum());
+            for (i, _, _) in &bounded {
+                entry_of[*i] = entries.len();
+            }
+            entries.push(None);
+            Some(LightTree::new(&mut bounded, refs.len()))
+        };
+
+        LightSampler {
+            lights: refs,
+            bounds: shape_bounds,
+            entries,
+            entry_of,
+            table: AliasTable::new(&weights),
+            tree,
+        }
+    }
+
+    pub fn lights(&self) -> &[LightRef] {
+        &self.lights
+    }
+
+    // Bounds of light i, if it is a shape that has them.
+    pub fn bounds(&self, i: usize) -> Option<&Aabb> {
+        self.bounds[i].as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // Picks a light for a shadow ray from p. Returns its index, the chance
+    // of picking it and u stretched back to [0,1).
+    pub fn pick(&self, p: Point3, u: f64) -> Option<(usize, f64, f64)> {
+        if self.is_empty() {
+            return None;
+        }
+        let (e, chance, u) = self.table.sample(u);
+        match self.entries[e] {
+            Some(i) => Some((i, chance, u)),
+            None => {
+                let (i, tree_chance, u) = self.tree.as_ref()?.pick(p, u);
+                Some((i, chance * tree_chance, u))
+            }
+        }
+    }
+
+    // Chance that pick chooses light i from p.
+    pub fn chance(&self, p: Point3, i: usize) -> f64 {
+        let e = self.entry_of[i];
+        let chance = self.table.pmf(e);
+        match (self.entries[e], &self.tree) {
+            (None, Some(tree)) => chance * tree.chance(p, i),
+            _ => chance,
+        }
+    }
+}
+
+// Lights carry no record of what they emit, since the shapes in the lights
+// list are only there to be sampled. Their power is estimated instead, from
+// the light they send to points spread through the scene, shadows included.
+fn estimate_power(
+    refs: &[LightRef],
+    world: &dyn Hittable,
+    lights: &HittableList,
+    punctual: &[PunctualLight],
+    background: &Background,
+) -> Vec<f64> {
+    let mut bounds = Aabb::default();
+    if refs.is_empty() || !world.bounding_box(0.0, 1.0, &mut bounds) {
+        return vec![1.0; refs.len()];
+    }
+
+    // Fixed seed, so the estimate is the same whatever the render seed.
+    let rng = &mut seeded_rng(0);
+    let mut power = vec![0.0; refs.len()];
+    for _ in 0..PILOT_POINTS {
+        let p = Point3::new(
+            random_double_range(bounds.min().x(), bounds.max().x(), rng),
+            random_double_range(bounds.min().y(), bounds.max().y(), rng),
+            random_double_range(bounds.min().z(), bounds.max().z(), rng),
+        );
+        for (i, light) in refs.iter().enumerate() {
+            let u = (random_double(rng), random_double(rng));
+            let li = match *light {
+                LightRef::Shape(j) => {
+                    let shape = &lights.objects[j];
+                    let direction = shape.random(p, u);
+                    let pdf = shape.pdf_value(p, direction, rng);
+                    let r = Ray::new(p, direction, 0.0);
+                    let mut rec = HitRecord::default();
+                    match world.hit(&r, 0.001, f64::INFINITY, &mut rec, rng) {
+                        Some(mat_ptr) if pdf > 0.0 => {
+                            mat_ptr.emitted(&r, &rec, rec.u, rec.v, rec.p) / pdf
+                        }
+                        _ => Color::new(0.0, 0.0, 0.0),
+                    }
+                }
+                LightRef::Environment => {
+                    let map = background.environment().unwrap();
+                    let direction = map.sample(u);
+                    let pdf = map.pdf(direction);
+                    let r = Ray::new(p, direction, 0.0);
+                    let mut rec = HitRecord::default();
+                    match world.hit(&r, 0.001, f64::INFINITY, &mut rec, rng) {
+                        None if pdf > 0.0 => map.radiance(direction) / pdf,
+                        _ => Color::new(0.0, 0.0, 0.0),
+                    }
+                }
+                LightRef::Punctual(j) => match punctual[j].sample_li(p, u) {
+                    Some(sample) => {
+                        let r = Ray::new(p, sample.direction, 0.0);
+                        let mut rec = HitRecord::default();
+                        match world.hit(&r, 0.001, sample.distance, &mut rec, rng) {
+                            None => sample.li,
+                            Some(_) => Color::new(0.0, 0.0, 0.0),
+                        }
+                    }
+                    None => Color::new(0.0, 0.0, 0.0),
+                },
+            };
+            if li.x().is_finite() && li.y().is_finite() && li.z().is_finite() {
+                power[i] += luminance(li).max(0.0);
+            }
+        }
+    }
+
+    // Every light keeps some chance, in case the pilot points all missed it.
+    let mean = power.iter().sum::<f64>() / power.len() as f64;
+    if mean <= 0.0 {
+        return vec![1.0; refs.len()];
+    }
+    power.iter().map(|p| p.max(1e-3 * mean)).collect()
+}
+
+struct TreeNode {
+    bounds: Aabb,
+    power: f64,
+    parent: Option<usize>,
+    // Either two children or the light of a leaf.
+    children: Option<(usize, usize)>,
+    light: usize,
+}
+
+// A binary tree over the lights that have bounds, in the spirit of
+// lightcuts. Going down, each child is picked by its power over its squared
+// distance to the shading point, so nearby lights are found even among many.
+struct LightTree {
+    nodes: Vec<TreeNode>,
+    leaf_of: Vec<Option<usize>>,
+}
+
+impl LightTree {
+    fn new(items: &mut [(usize, Aabb, f64)], light_count: usize) -> LightTree {
+        let mut tree = LightTree {
+            nodes: Vec::with_capacity(2 * items.len()),
+            leaf_of: vec![None; light_count],
+        };
+        tree.build(items, None);
+        tree
+    }
+
+    fn build(&mut self, items: &mut [(usize, Aabb, f64)], parent: Option<usize>) -> usize {
+        let id = self.nodes.len();
+        let bounds = items
+            .iter()
+            .skip(1)
+            .fold(items[0].1, |b, item| surrounding_box(&b, &item.1));
+        self.nodes.push(TreeNode {
+            bounds,
+            power: items.iter().map(|item| item.2).sum(),
+            parent,
+            children: None,
+            light: items[0].0,
+        });
+        if items.len() == 1 {
+            self.leaf_of[items[0].0] = Some(id);
+            return id;
+        }
+
+        // Split at the median along the widest spread of centers.
+        let center = |b: &Aabb| 0.5 * (b.min() + b.max());
+        let mut lo = center(&items[0].1);
+        let mut hi = lo;
+        for item in items.iter() {
+            let c = center(&item.1);
+            lo = Point3::new(lo.x().min(c.x()), lo.y().min(c.y()), lo.z().min(c.z()));
+            hi = Point3::new(hi.x().max(c.x()), hi.y().max(c.y()), hi.z().max(c.z()));
+        }
+        let extent = hi - lo;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        items.sort_by(|a, b| total_cmp(center(&a.1)[axis], center(&b.1)[axis]));
+        let mid = items.len() / 2;
+        let (left, right) = items.split_at_mut(mid);
+        let left = self.build(left, Some(id));
+        let right = self.build(right, Some(id));
+        self.nodes[id].children = Some((left, right));
+        id
+    }
+
+    fn importance(&self, node: usize, p: Point3) -> f64 {
+        let node = &self.nodes[node];
+        let center = 0.5 * (node.bounds.min() + node.bounds.max());
+        // Inside the bounds the distance means little, so it is held at the
+        // bounds' radius.
+        let radius_squared = 0.25 * (node.bounds.max() - node.bounds.min()).length_squared();
+        let distance_squared = (p - center).length_squared();
+        node.power / distance_squared.max(radius_squared).max(1e-8)
+    }
+
+    // Chance of going from node to its left child.
+    fn left_chance(&self, node: usize, p: Point3) -> f64 {
+        let (left, right) = self.nodes[node].children.unwrap();
+        let l = self.importance(left, p);
+        let r = self.importance(right, p);
+        if l + r > 0.0 {
+            l / (l + r)
+        } else {
+            0.5
+        }
+    }
+
+    fn pick(&self, p: Point3, u: f64) -> (usize, f64, f64) {
+        let mut node = 0;
+        let mut chance = 1.0;
+        let mut u = u;
+        while let Some((left, right)) = self.nodes[node].children {
+            let pl = self.left_chance(node, p);
+            if u < pl {
+                u /= pl;
+                chance *= pl;
+                node = left;
+            } else {
+                u = (u - pl) / (1.0 - pl);
+                chance *= 1.0 - pl;
+                node = right;
+            }
+            u = u.min(1.0 - f64::EPSILON);
+        }
+        (self.nodes[node].light, chance, u)
+    }
+
+    fn chance(&self, p: Point3, light: usize) -> f64 {
+        let mut node = match self.leaf_of[light] {
+            Some(node) => node,
+            None => return 0.0,
+        };
+        let mut chance = 1.0;
+        while let Some(parent) = self.nodes[node].parent {
+            let pl = self.left_chance(parent, p);
+            let (left, _) = self.nodes[parent].children.unwrap();
+            chance *= if node == left { pl } else { 1.0 - pl };
+            node = parent;
+        }
+        chance
+    }
+}
@@ -1,39 +1,461 @@
-mod canny;
-use canny::*;
-
 use console::style;
+use image::DynamicImage;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::{fs::File, process::exit};
 
-fn main() {
-    let path = std::path::Path::new("output/bonus/edge/edge.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+use raytracer::canny::*;
+use raytracer::{checkpoint, scene_file};
+use raytracer::{Filter, Framebuffer, RenderState, SamplerKind, Scene, ToneMapper};
 
-    // Draw
-    let quality = 100;
-    let img = image::open("input/edge.jpg").unwrap().to_rgb8();
-    let gray_img = image::open("input/edge.jpg").unwrap().to_luma8();
+const USAGE: &str = "Usage:
+    raytracer render <scene> [-o <output>] [--spp <n>] [--width <n>] [--depth <n>] [--threads <n>] [--seed <n>]
+                            [--rr-depth <n>] [--diffuse-depth <n>] [--specular-depth <n>] [--transmission-depth <n>]
+                            [--sampler <name>] [--filter <name>] [--filter-radius <px>]
+                            [--tonemap <op>] [--exposure <ev>] [--white <l>]
+                            [--target-error <e>] [--pass-spp <n>] [--min-spp <n>] [--mis <name>]
+                            [--light-sampling <name>]
+                            [--checkpoint <file>] [--checkpoint-interval <s>]
+    raytracer render --resume <file> [-o <output>] [--spp <n>] [--threads <n>]
+    raytracer edges <image> [-o <dir>] [--threshold <n>]
+
+<scene> is a built-in scene or a .json/.yaml/.yml scene file.
+.exr and .hdr outputs keep the unclamped linear radiance.
+With --target-error, pixels stop sampling once their relative standard error
+drops below it, and --spp becomes the maximum. The output is rewritten after
+every pass, so a long render can be stopped at any point.
+With --checkpoint, the render state is saved at the end of a pass once every
+--checkpoint-interval seconds (60 by default) and when the render finishes.
+--resume carries on from a checkpoint with the same scene and options, and
+gives the same image as a render that was never stopped. A higher --spp adds
+samples to a finished render, except with the stratified sampler, whose strata
+depend on the sample count.
+Past --rr-depth bounces (3 by default), Russian roulette ends paths that
+carry little light. --diffuse-depth, --specular-depth and --transmission-depth
+limit each kind of bounce on top of --depth.
+--light-sampling decides which light each shadow ray goes to: power picks
+lights by their estimated power, tree also favours lights close to the point
+being shaded.
+Built-in scenes: cornell_box, final_scene
+Samplers: independent, stratified, halton, sobol (default), blue_noise
+Filters: box (default), tent, gaussian, mitchell
+Tone mapping operators: clamp, reinhard, reinhard_extended, aces
+MIS heuristics: balance, power (default)
+Light sampling: uniform, power (default), tree";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", style(msg).red());
+    eprintln!("{}", USAGE);
+    exit(1);
+}
+
+#[derive(Clone)]
+struct Args {
+    command: String,
+    target: Option<String>,
+    output: Option<PathBuf>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    fn parse() -> Args {
+        Args::parse_from(std::env::args().skip(1))
+    }
+
+    fn parse_from<I: Iterator<Item = String>>(mut args: I) -> Args {
+        let command = args.next().unwrap_or_else(|| String::from("render"));
+        let mut target = None;
+        let mut output = None;
+        let mut options = Vec::new();
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            if arg == "-o" || arg == "--output" {
+                match args.next() {
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => fail("Missing value for -o."),
+                }
+            } else if let Some(key) = arg.strip_prefix("--") {
+                match args.next() {
+                    Some(value) => options.push((String::from(key), value)),
+                    None => fail(&format!("Missing value for --{}.", key)),
+                }
+            } else if target.is_none() {
+                target = Some(arg);
+            } else {
+                fail(&format!("Unexpected argument \"{}\".", arg));
+            }
+        }
+
+        Args {
+            command,
+            target,
+            output,
+            options,
+        }
+    }
+
+    // A command line that parses back to the same arguments.
+    fn to_argv(&self) -> Vec<String> {
+        let mut argv = vec![self.command.clone()];
+        if let Some(target) = &self.target {
+            argv.push(target.clone());
+        }
+        if let Some(output) = &self.output {
+            argv.push(String::from("-o"));
+            argv.push(output.to_string_lossy().into_owned());
+        }
+        for (key, value) in &self.options {
+            argv.push(format!("--{}", key));
+            argv.push(value.clone());
+        }
+        argv
+    }
+
+    fn value<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| match v.parse() {
+                Ok(value) => value,
+                Err(_) => fail(&format!("Invalid value \"{}\" for --{}.", v, key)),
+            })
+    }
+
+    // Replaces every earlier value of the option.
+    fn set(&mut self, key: &str, value: String) {
+        self.options.retain(|(k, _)| k != key);
+        self.options.push((String::from(key), value));
+    }
+
+    fn check_options(&self, known: &[&str]) {
+        for (key, _) in &self.options {
+            if !known.contains(&key.as_str()) {
+                fail(&format!("Unknown option --{}.", key));
+            }
+        }
+    }
+}
 
-    let gray_img = canny_edge_detection(&gray_img, 10);
-    let img = write_edge(&img, &gray_img);
+fn save_image(img: DynamicImage, path: &Path) {
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+    }
 
     println!(
         "Ouput image as \"{}\"",
         style(path.to_str().unwrap()).yellow()
     );
-    let output_image = image::DynamicImage::ImageRgb8(img);
-    let mut output_file = File::create("output/bonus/edge/image_color.jpg").unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
+    let quality = 100;
+    let result = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("jpg") | Some("jpeg") => File::create(path)
+            .map_err(image::ImageError::IoError)
+            .and_then(|mut output_file| {
+                img.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality))
+            }),
+        _ => img.save(path),
+    };
+    match result {
         Ok(_) => {}
         Err(_) => println!("{}", style("Outputting image fails.").red()),
     }
+}
+
+// .exr and .hdr keep the linear radiance, anything else is 8-bit.
+fn save_film(film: &Framebuffer, path: &Path, tone_map: &ToneMapper) {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if extension != Some("exr") && extension != Some("hdr") {
+        return save_image(DynamicImage::ImageRgb8(film.to_rgb8(tone_map)), path);
+    }
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+    }
 
-    let output_image = image::DynamicImage::ImageLuma8(gray_img);
-    let mut output_file = File::create("output/bonus/edge/image_edge.jpg").unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
+    println!(
+        "Ouput image as \"{}\"",
+        style(path.to_str().unwrap()).yellow()
+    );
+    let result = File::create(path).and_then(|file| {
+        let mut w = BufWriter::new(file);
+        if extension == Some("exr") {
+            film.write_exr(&mut w)?;
+        } else {
+            film.write_hdr(&mut w)?;
+        }
+        w.flush()
+    });
+    match result {
         Ok(_) => {}
         Err(_) => println!("{}", style("Outputting image fails.").red()),
     }
+}
+
+// The arguments saved in a checkpoint with the ones given now on top. The
+// checkpoint itself keeps being updated unless --checkpoint says otherwise.
+fn resume(args: &Args, path: &Path) -> (Args, Args, RenderState) {
+    if args.target.is_some() {
+        fail("--resume takes the scene from the checkpoint.");
+    }
+    let saved = match checkpoint::load(path) {
+        Ok(saved) => saved,
+        Err(err) => fail(&format!(
+            "Cannot read checkpoint \"{}\": {}",
+            path.display(),
+            err
+        )),
+    };
+
+    let original = Args::parse_from(saved.args.into_iter());
+    let mut resumed = original.clone();
+    resumed.set("checkpoint", path.to_string_lossy().into_owned());
+    for (key, value) in &args.options {
+        if key != "resume" {
+            resumed.set(key, value.clone());
+        }
+    }
+    if args.output.is_some() {
+        resumed.output = args.output.clone();
+    }
+    (original, resumed, saved.state)
+}
+
+fn save_checkpoint(path: &Path, args: &Args, state: &RenderState) {
+    println!(
+        "Checkpoint as \"{}\"",
+        style(path.to_str().unwrap()).yellow()
+    );
+    if checkpoint::save(path, &args.to_argv(), state).is_err() {
+        println!("{}", style("Saving the checkpoint fails.").red());
+    }
+}
+
+fn render(args: &Args) {
+    args.check_options(&[
+        "spp",
+        "width",
+        "depth",
+        "rr-depth",
+        "diffuse-depth",
+        "specular-depth",
+        "transmission-depth",
+        "threads",
+        "seed",
+        "sampler",
+        "filter",
+        "filter-radius",
+        "tonemap",
+        "exposure",
+        "white",
+        "target-error",
+        "pass-spp",
+        "min-spp",
+        "mis",
+        "light-sampling",
+        "checkpoint",
+        "checkpoint-interval",
+        "resume",
+    ]);
+
+    // The arguments the checkpoint was started with, when resuming.
+    let (original, args, state) = match args.value::<PathBuf>("resume") {
+        Some(path) => {
+            let (original, args, state) = resume(args, &path);
+            (Some(original), args, Some(state))
+        }
+        None => (None, args.clone(), None),
+    };
+
+    let name = args.target.as_deref().unwrap_or("cornell_box");
+    let mut scene = match Scene::builtin(name) {
+        Some(scene) => scene,
+        None if Path::new(name).is_file() => match scene_file::load(name) {
+            Ok(scene) => scene,
+            Err(err) => fail(&err.to_string()),
+        },
+        None => fail(&format!("Unknown scene \"{}\".", name)),
+    };
+    let scene_spp = scene.samples_per_pixel;
+    if let Some(spp) = args.value("spp") {
+        scene.samples_per_pixel = spp;
+    }
+    if let Some(width) = args.value("width") {
+        scene.image_width = width;
+    }
+    if scene.image_width < 1 || scene.image_height() < 1 {
+        fail("The image must be at least one pixel wide and tall.");
+    }
+    if scene.samples_per_pixel < 1 {
+        fail("At least one sample per pixel is needed.");
+    }
+    if let Some(depth) = args.value("depth") {
+        scene.max_depth = depth;
+    }
+    if let Some(depth) = args.value("rr-depth") {
+        scene.roulette_depth = depth;
+    }
+    if let Some(depth) = args.value("diffuse-depth") {
+        scene.diffuse_depth = Some(depth);
+    }
+    if let Some(depth) = args.value("specular-depth") {
+        scene.specular_depth = Some(depth);
+    }
+    if let Some(depth) = args.value("transmission-depth") {
+        scene.transmission_depth = Some(depth);
+    }
+    if let Some(seed) = args.value("seed") {
+        scene.seed = seed;
+    }
+    if let Some(sampler) = args.value("sampler") {
+        scene.sampler = sampler;
+    }
+    if let Some(kind) = args.value("filter") {
+        scene.filter = Filter::new(kind);
+    }
+    if let Some(radius) = args.value::<f64>("filter-radius") {
+        if radius <= 0.0 {
+            fail("--filter-radius must be positive.");
+        }
+        scene.filter.radius = radius;
+    }
+    if let Some(operator) = args.value("tonemap") {
+        scene.tone_map.operator = operator;
+    }
+    if let Some(exposure) = args.value("exposure") {
+        scene.tone_map.exposure = exposure;
+    }
+    if let Some(white) = args.value("white") {
+        scene.tone_map.white = white;
+    }
+    if let Some(target_error) = args.value("target-error") {
+        scene.target_error = target_error;
+    }
+    if let Some(pass_samples) = args.value("pass-spp") {
+        scene.pass_samples = pass_samples;
+    }
+    if let Some(min_samples) = args.value("min-spp") {
+        scene.min_samples = min_samples;
+    }
+    if let Some(mis) = args.value("mis") {
+        scene.mis = mis;
+    }
+    if let Some(light_sampling) = args.value("light-sampling") {
+        scene.light_sampling = light_sampling;
+    }
+    if let Some(original) = &original {
+        // Stratified samples are spread over strata made for the sample
+        // count, so a different count isn't a continuation of the render.
+        let started_with = original.value("spp").unwrap_or(scene_spp);
+        if scene.sampler == SamplerKind::Stratified && scene.samples_per_pixel != started_with {
+            fail("--spp can't change when resuming a render with the stratified sampler.");
+        }
+    }
+
+    let path = match &args.output {
+        Some(path) => path.clone(),
+        None => {
+            let stem = Path::new(name).file_stem().unwrap().to_str().unwrap();
+            PathBuf::from(format!("output/{}.jpg", stem))
+        }
+    };
+
+    let mut renderer = scene.renderer();
+    if let Some(threads) = args.value("threads") {
+        renderer.thread_num = threads;
+    }
+    let state = match state {
+        Some(state) => {
+            if state.film.width != renderer.image_width
+                || state.film.height != renderer.image_height
+            {
+                fail("The checkpoint was made for a different image size.");
+            }
+            state
+        }
+        None => RenderState::new(renderer.image_width, renderer.image_height),
+    };
+
+    let checkpoint_path = args.value::<PathBuf>("checkpoint");
+    let interval = args.value::<f64>("checkpoint-interval").unwrap_or(60.0);
+    if interval < 0.0 || !interval.is_finite() {
+        fail("--checkpoint-interval must not be negative.");
+    }
+    let interval = Duration::from_secs_f64(interval);
+    let mut last_checkpoint = Instant::now();
+
+    let state = renderer.render_progressive(
+        &scene.camera,
+        &scene.world,
+        &scene.lights,
+        &scene.punctual_lights,
+        &scene.background,
+        state,
+        |state| {
+            // Every pass but the last leaves a preview behind.
+            if state.samples_done < renderer.samples_per_pixel {
+                save_film(&state.film, &path, &scene.tone_map);
+            }
+            if let Some(checkpoint_path) = &checkpoint_path {
+                if last_checkpoint.elapsed() >= interval {
+                    save_checkpoint(checkpoint_path, &args, state);
+                    last_checkpoint = Instant::now();
+                }
+            }
+        },
+    );
+    save_film(&state.film, &path, &scene.tone_map);
+    if let Some(checkpoint_path) = &checkpoint_path {
+        save_checkpoint(checkpoint_path, &args, &state);
+    }
+    println!(
+        "{} paths, {} on average",
+        state.path_stats.paths,
+        style(format!("{:.2} bounces", state.path_stats.average_length())).yellow()
+    );
+}
+
+fn edges(args: &Args) {
+    args.check_options(&["threshold"]);
+
+    let input = match &args.target {
+        Some(input) => input,
+        None => fail("Missing input image."),
+    };
+    let img = match image::open(input) {
+        Ok(img) => img,
+        Err(err) => fail(&format!("Cannot open \"{}\": {}", input, err)),
+    };
+    let threshold = args.value("threshold").unwrap_or(10);
+
+    let gray_img = canny_edge_detection(&img.to_luma8(), threshold);
+    let color_img = write_edge(&img.to_rgb8(), &gray_img);
+
+    let dir = match &args.output {
+        Some(dir) => dir.clone(),
+        None => PathBuf::from("output/bonus/edge"),
+    };
+    save_image(
+        DynamicImage::ImageRgb8(color_img),
+        &dir.join("image_color.jpg"),
+    );
+    save_image(
+        DynamicImage::ImageLuma8(gray_img),
+        &dir.join("image_edge.jpg"),
+    );
+}
+
+fn main() {
+    let args = Args::parse();
+    match args.command.as_str() {
+        "render" => render(&args),
+        "edges" => edges(&args),
+        "-h" | "--help" | "help" => println!("{}", USAGE),
+        command => fail(&format!("Unknown command \"{}\".", command)),
+    }
 
     exit(0);
 }
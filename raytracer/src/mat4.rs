@@ -0,0 +1,234 @@
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// Row-major 4x4 matrix acting on column vectors, so `a * b` applies b first.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translate(offset: Vec3) -> Mat4 {
+        Mat4::new([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scale(s: Vec3) -> Mat4 {
+        Mat4::new([
+            [s.x(), 0.0, 0.0, 0.0],
+            [0.0, s.y(), 0.0, 0.0],
+            [0.0, 0.0, s.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotate_x(angle: f64) -> Mat4 {
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        Mat4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, cos_theta, -sin_theta, 0.0],
+            [0.0, sin_theta, cos_theta, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // Same sense as RotateY.
+    pub fn rotate_y(angle: f64) -> Mat4 {
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        Mat4::new([
+            [cos_theta, 0.0, sin_theta, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [-sin_theta, 0.0, cos_theta, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotate_z(angle: f64) -> Mat4 {
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        Mat4::new([
+            [cos_theta, -sin_theta, 0.0, 0.0],
+            [sin_theta, cos_theta, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // Rodrigues' rotation about an arbitrary axis through the origin.
+    pub fn rotate(axis: Vec3, angle: f64) -> Mat4 {
+        let a = unit_vector(axis);
+        let (s, c) = degrees_to_radians(angle).sin_cos();
+        let t = 1.0 - c;
+        Mat4::new([
+            [
+                t * a.x() * a.x() + c,
+                t * a.x() * a.y() - s * a.z(),
+                t * a.x() * a.z() + s * a.y(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.y() + s * a.z(),
+                t * a.y() * a.y() + c,
+                t * a.y() * a.z() - s * a.x(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.z() - s * a.y(),
+                t * a.y() * a.z() + s * a.x(),
+                t * a.z() * a.z() + c,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = [[0.0; 4]; 4];
+        for (i, row) in t.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.m[j][i];
+            }
+        }
+        Mat4::new(t)
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. None for singular
+    // matrices and ones with an infinite or NaN entry.
+    pub fn inverse(&self) -> Option<Mat4> {
+        if self.m.iter().flatten().any(|x| !x.is_finite()) {
+            return None;
+        }
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| total_cmp(a[i][col].abs(), a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let d = a[col][col];
+            for k in 0..4 {
+                a[col][k] /= d;
+                inv[col][k] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= f * a[col][k];
+                        inv[row][k] -= f * inv[col][k];
+                    }
+                }
+            }
+        }
+        // Elimination can still overflow on huge entries.
+        if inv.iter().flatten().any(|x| !x.is_finite()) {
+            return None;
+        }
+        Some(Mat4::new(inv))
+    }
+
+    // Determinant of the upper 3x3 part, i.e. how the transform scales volume.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        let mut out = Point3::default();
+        for (i, row) in m.iter().take(3).enumerate() {
+            out[i] = row[0] * p.x() + row[1] * p.y() + row[2] * p.z() + row[3];
+        }
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w != 1.0 && w != 0.0 {
+            out / w
+        } else {
+            out
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let mut out = Vec3::default();
+        for (i, row) in self.m.iter().take(3).enumerate() {
+            out[i] = row[0] * v.x() + row[1] * v.y() + row[2] * v.z();
+        }
+        out
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::identity()
+    }
+}
+
+impl std::ops::Mul for Mat4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4::new(m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Mat4, b: Mat4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a.m[i][j] - b.m[i][j]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let m = Mat4::translate(Vec3::new(1.0, -2.0, 3.0))
+            * Mat4::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scale(Vec3::new(2.0, 0.5, 4.0));
+        let inv = m.inverse().unwrap();
+        assert_near(m * inv, Mat4::identity());
+        assert_near(inv * m, Mat4::identity());
+    }
+
+    #[test]
+    fn inverse_rejects_singular_and_non_finite_matrices() {
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Mat4::scale(Vec3::new(f64::NAN, 1.0, 1.0))
+            .inverse()
+            .is_none());
+        let mut m = Mat4::identity();
+        m.m[0][3] = f64::INFINITY;
+        assert!(m.inverse().is_none());
+    }
+}
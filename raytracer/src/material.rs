@@ -1,7 +1,12 @@
 use std::f64::consts::PI;
+use std::sync::Arc;
 
+use crate::color::*;
+use crate::environment::*;
 use crate::hittable::*;
-// use crate::onb::*;
+use crate::ies::*;
+use crate::microfacet::*;
+use crate::onb::*;
 use crate::pdf::*;
 use crate::ray::*;
 use crate::rtweekend::*;
@@ -20,19 +25,59 @@ pub trait Material: Send + Sync {
         _r_in: &Ray,
         _rec: &HitRecord,
         _srec: &mut ScatterRecord,
+        _rng: &mut RandGen,
     ) -> (bool, Option<Box<dyn Pdf>>) {
         (false, None)
     }
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         0.0
     }
+    // The BSDF times the cosine, which weights a pdf-sampled bounce. Materials
+    // whose color doesn't change with direction need only scattering_pdf.
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        srec.attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
 
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: Point3) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
 }
 
-#[derive(Default)]
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        rng: &mut RandGen,
+    ) -> (bool, Option<Box<dyn Pdf>>) {
+        (**self).scatter(r_in, rec, srec, rng)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        (**self).scattering_pdf(r_in, rec, scattered)
+    }
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        (**self).scattering(r_in, rec, srec, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        (**self).emitted(r_in, rec, u, v, p)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
 pub struct Empty {}
 impl Material for Empty {}
 
@@ -59,6 +104,7 @@ impl<T: Texture> Material for Lambertian<T> {
         _r_in: &Ray,
         rec: &HitRecord,
         srec: &mut ScatterRecord,
+        _rng: &mut RandGen,
     ) -> (bool, Option<Box<dyn Pdf>>) {
         srec.attenuation = self.albedo.value(rec.u, rec.v, rec.p);
         (true, Some(Box::new(CosinePdf::new(rec.normal))))
@@ -98,9 +144,14 @@ impl Material for Metal {
         r_in: &Ray,
         rec: &HitRecord,
         srec: &mut ScatterRecord,
+        rng: &mut RandGen,
     ) -> (bool, Option<Box<dyn Pdf>>) {
         let reflected = reflect(unit_vector(r_in.direction()), rec.normal);
-        srec.specular_ray = Ray::new(rec.p, reflected + self.fuzz * random_in_unit_sphere(), 0.0);
+        srec.specular_ray = Ray::new(
+            rec.p,
+            reflected + self.fuzz * random_in_unit_sphere(rng),
+            0.0,
+        );
         srec.attenuation = self.albedo;
         (true, None)
     }
@@ -129,6 +180,7 @@ impl Material for Dielectric {
         r_in: &Ray,
         rec: &HitRecord,
         srec: &mut ScatterRecord,
+        rng: &mut RandGen,
     ) -> (bool, Option<Box<dyn Pdf>>) {
         srec.attenuation = Color::new(1.0, 1.0, 1.0);
 
@@ -146,7 +198,7 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let direction = {
-            if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_double() {
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_double(rng) {
                 reflect(unit_direction, rec.normal)
             } else {
                 refract(unit_direction, rec.normal, refraction_ratio)
@@ -158,28 +210,323 @@ impl Material for Dielectric {
     }
 }
 
+// Scalar parameters read the mean of their texture's channels.
+fn scalar_value<T: Texture + ?Sized>(t: &T, rec: &HitRecord) -> f64 {
+    let c = t.value(rec.u, rec.v, rec.p);
+    clamp((c.x() + c.y() + c.z()) / 3.0, 0.0, 1.0)
+}
+
+// Shading frame around the normal, and the direction back along the ray in it.
+fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let mut uvw = Onb::default();
+    uvw.build_from_w(rec.normal);
+    let wo = uvw.to_local(-unit_vector(r_in.direction()));
+    (uvw, wo)
+}
+
+// GGX microfacet metal. eta and k are the real and imaginary parts of the
+// index of refraction per channel.
 #[derive(Clone, Copy, Default)]
+pub struct RoughConductor<T: Texture> {
+    eta: Color,
+    k: Color,
+    roughness: T,
+}
+impl<T: Texture> RoughConductor<T> {
+    pub fn mv(eta: Color, k: Color, roughness: T) -> RoughConductor<T> {
+        RoughConductor { eta, k, roughness }
+    }
+}
+impl RoughConductor<SolidColor> {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> RoughConductor<SolidColor> {
+        RoughConductor {
+            eta,
+            k,
+            roughness: SolidColor::new(Color::new(roughness, roughness, roughness)),
+        }
+    }
+}
+impl<T: Texture> RoughConductor<T> {
+    fn fresnel(&self, cos_i: f64) -> Color {
+        Color::new(
+            fr_conductor(cos_i, self.eta.x(), self.k.x()),
+            fr_conductor(cos_i, self.eta.y(), self.k.y()),
+            fr_conductor(cos_i, self.eta.z(), self.k.z()),
+        )
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord) -> MicrofacetPdf {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        MicrofacetPdf {
+            uvw,
+            wo,
+            ggx: Ggx::from_roughness(scalar_value(&self.roughness, rec)),
+            eta: None,
+            select: 0.0,
+        }
+    }
+}
+impl<T: Texture> Material for RoughConductor<T> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        _rng: &mut RandGen,
+    ) -> (bool, Option<Box<dyn Pdf>>) {
+        let pdf = self.pdf(r_in, rec);
+        if pdf.ggx.is_smooth() {
+            let wi = reflect_wi(pdf.wo, Vec3::new(0.0, 0.0, 1.0));
+            srec.specular_ray = Ray::new(rec.p, pdf.uvw.local(wi), r_in.time());
+            srec.attenuation = self.fresnel(pdf.wo.z());
+            return (true, None);
+        }
+        srec.attenuation = Color::new(1.0, 1.0, 1.0);
+        (true, Some(Box::new(pdf)))
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.pdf(r_in, rec).density(scattered.direction())
+    }
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        let pdf = self.pdf(r_in, rec);
+        let wo = pdf.wo;
+        let wi = pdf.uvw.to_local(unit_vector(scattered.direction()));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let wh = unit_vector(wo + wi);
+        let ggx = pdf.ggx;
+        ggx.d(wh) * ggx.g(wo, wi) / (4.0 * wo.z()) * self.fresnel(dot(wo, wh))
+    }
+}
+
+// GGX microfacet glass.
+#[derive(Clone, Copy, Default)]
+pub struct RoughDielectric<T: Texture> {
+    ir: f64,
+    roughness: T,
+}
+impl<T: Texture> RoughDielectric<T> {
+    pub fn mv(index_of_refraction: f64, roughness: T) -> RoughDielectric<T> {
+        RoughDielectric {
+            ir: index_of_refraction,
+            roughness,
+        }
+    }
+}
+impl RoughDielectric<SolidColor> {
+    pub fn new(index_of_refraction: f64, roughness: f64) -> RoughDielectric<SolidColor> {
+        RoughDielectric {
+            ir: index_of_refraction,
+            roughness: SolidColor::new(Color::new(roughness, roughness, roughness)),
+        }
+    }
+}
+impl<T: Texture> RoughDielectric<T> {
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, select: f64) -> MicrofacetPdf {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        MicrofacetPdf {
+            uvw,
+            wo,
+            ggx: Ggx::from_roughness(scalar_value(&self.roughness, rec)),
+            eta: Some(if rec.front_face {
+                self.ir
+            } else {
+                1.0 / self.ir
+            }),
+            select,
+        }
+    }
+}
+impl<T: Texture> Material for RoughDielectric<T> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        rng: &mut RandGen,
+    ) -> (bool, Option<Box<dyn Pdf>>) {
+        srec.attenuation = Color::new(1.0, 1.0, 1.0);
+        let pdf = self.pdf(r_in, rec, random_double(rng));
+        if pdf.ggx.is_smooth() {
+            let n = Vec3::new(0.0, 0.0, 1.0);
+            let wi = pdf.sample_about(n);
+            srec.specular_ray = Ray::new(rec.p, pdf.uvw.local(wi), r_in.time());
+            return (true, None);
+        }
+        (true, Some(Box::new(pdf)))
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.pdf(r_in, rec, 0.0).density(scattered.direction())
+    }
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        let pdf = self.pdf(r_in, rec, 0.0);
+        let (wo, ggx, eta) = (pdf.wo, pdf.ggx, pdf.eta.unwrap());
+        let wi = pdf.uvw.to_local(unit_vector(scattered.direction()));
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let f = if wi.z() > 0.0 {
+            let wh = unit_vector(wo + wi);
+            ggx.d(wh) * ggx.g(wo, wi) * fr_dielectric(dot(wo, wh), eta) / (4.0 * wo.z())
+        } else {
+            ggx.transmission(wo, wi, eta)
+        };
+        f * Color::new(1.0, 1.0, 1.0)
+    }
+}
+
+// Samples the visible normals of a GGX surface and reflects off them or, for
+// a dielectric with relative index eta, refracts through them as often as the
+// Fresnel term says.
+pub struct MicrofacetPdf {
+    uvw: Onb,
+    wo: Vec3,
+    ggx: Ggx,
+    eta: Option<f64>,
+    // Decides between reflection and refraction, which the 2D sample can't.
+    select: f64,
+}
+impl MicrofacetPdf {
+    fn reflect_chance(&self, wm: Vec3) -> f64 {
+        match self.eta {
+            Some(eta) => fr_dielectric(dot(self.wo, wm), eta),
+            None => 1.0,
+        }
+    }
+    fn density(&self, direction: Vec3) -> f64 {
+        let wo = self.wo;
+        let wi = self.uvw.to_local(unit_vector(direction));
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        let wh = wo + wi;
+        if wh.length_squared() > 0.0 {
+            let wh = unit_vector(wh);
+            let cos_d = dot(wo, wh);
+            if wh.z() > 0.0 && cos_d > 0.0 {
+                pdf += self.reflect_chance(wh) * self.ggx.pdf(wo, wh) / (4.0 * cos_d);
+            }
+        }
+        if let Some(eta) = self.eta {
+            if wi.z() < 0.0 {
+                if let Some(wm) = refraction_wm(wo, wi, eta) {
+                    pdf += (1.0 - self.reflect_chance(wm))
+                        * self.ggx.pdf(wo, wm)
+                        * refraction_jacobian(wo, wi, wm, eta);
+                }
+            }
+        }
+        pdf
+    }
+    fn sample_about(&self, wm: Vec3) -> Vec3 {
+        if let Some(eta) = self.eta {
+            if self.select >= self.reflect_chance(wm) {
+                if let Some(wi) = refract_wi(self.wo, wm, eta) {
+                    return wi;
+                }
+            }
+        }
+        reflect_wi(self.wo, wm)
+    }
+}
+impl Pdf for MicrofacetPdf {
+    fn value(&self, direction: Vec3, _rng: &mut RandGen) -> f64 {
+        self.density(direction)
+    }
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        let wm = self.ggx.sample_wm(self.wo, u);
+        self.uvw.local(self.sample_about(wm))
+    }
+}
+
+// How an emitter's brightness varies with the direction it is seen from,
+// relative to its normal.
+#[derive(Clone)]
+pub enum EmissionProfile {
+    // Vertical angles from the normal, horizontal ones around it from an
+    // arbitrary but fixed tangent.
+    Ies(Arc<IesProfile>),
+    // A lat-long map with the normal at its top, which tints as well as
+    // scales.
+    Goniometric(Arc<EnvironmentMap>),
+}
+
+impl EmissionProfile {
+    fn value(&self, normal: Vec3, direction: Vec3) -> Color {
+        let mut uvw = Onb::default();
+        uvw.build_from_w(normal);
+        let d = uvw.to_local(unit_vector(direction));
+        match self {
+            EmissionProfile::Ies(profile) => {
+                let vertical = d.z().clamp(-1.0, 1.0).acos().to_degrees();
+                let horizontal = d.y().atan2(d.x()).to_degrees();
+                let value = profile.value(vertical, horizontal);
+                Color::new(value, value, value)
+            }
+            EmissionProfile::Goniometric(map) => map.radiance(Vec3::new(d.x(), d.z(), d.y())),
+        }
+    }
+}
+
+// Emits from its front face only unless it is two-sided. The profile scales
+// radiance, so the intensity of a small emitter follows the profile times the
+// cosine to its normal.
+#[derive(Clone, Default)]
 pub struct DiffuseLight<T: Texture> {
     emit: T,
+    two_sided: bool,
+    profile: Option<EmissionProfile>,
 }
 impl<T: Texture> DiffuseLight<T> {
-    pub fn _mv(emit: T) -> DiffuseLight<T> {
-        DiffuseLight { emit }
+    pub fn mv(emit: T) -> DiffuseLight<T> {
+        DiffuseLight {
+            emit,
+            two_sided: false,
+            profile: None,
+        }
+    }
+    pub fn with_two_sided(mut self, two_sided: bool) -> DiffuseLight<T> {
+        self.two_sided = two_sided;
+        self
+    }
+    pub fn with_profile(mut self, profile: EmissionProfile) -> DiffuseLight<T> {
+        self.profile = Some(profile);
+        self
     }
 }
 impl DiffuseLight<SolidColor> {
     pub fn new(c: Color) -> DiffuseLight<SolidColor> {
-        DiffuseLight {
-            emit: SolidColor::new(c),
-        }
+        DiffuseLight::mv(SolidColor::new(c))
+    }
+    // The color of a black body at temperature kelvin, with the given
+    // luminance.
+    pub fn blackbody(temperature: f64, luminance: f64) -> DiffuseLight<SolidColor> {
+        DiffuseLight::new(luminance * blackbody(temperature))
     }
 }
 impl<T: Texture> Material for DiffuseLight<T> {
-    fn emitted(&self, _r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
-        if rec.front_face {
-            self.emit.value(u, v, p)
-        } else {
-            Color::new(0.0, 0.0, 0.0)
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        if !rec.front_face && !self.two_sided {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let emit = self.emit.value(u, v, p);
+        match &self.profile {
+            // The normal faces the ray, so it is on the side being seen.
+            Some(profile) => emit * profile.value(rec.normal, -r_in.direction()),
+            None => emit,
         }
     }
 }
@@ -194,7 +541,7 @@ impl<T: Texture> Isotropic<T> {
             albedo: SolidColor::new(c),
         }
     }
-    pub fn _mv(a: T) -> Isotropic<T> {
+    pub fn mv(a: T) -> Isotropic<T> {
         Isotropic { albedo: a }
     }
 }
@@ -204,9 +551,335 @@ impl<T: Texture> Material for Isotropic<T> {
         _r_in: &Ray,
         rec: &HitRecord,
         srec: &mut ScatterRecord,
+        rng: &mut RandGen,
     ) -> (bool, Option<Box<dyn Pdf>>) {
-        srec.specular_ray = Ray::new(rec.p, random_unit_vector(), 0.0);
+        srec.specular_ray = Ray::new(rec.p, random_unit_vector(rng), 0.0);
         srec.attenuation = self.albedo.value(rec.u, rec.v, rec.p);
         (true, None)
     }
 }
+
+// Burley's principled BSDF: a diffuse base with retro-reflection, a
+// subsurface approximation and sheen, a GGX specular lobe that can be metal
+// or dielectric and may transmit, and a GTR1 clear coat.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub subsurface: Arc<dyn Texture>,
+    pub ior: f64,
+}
+
+fn constant(x: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Color::new(x, x, x)))
+}
+
+impl Principled {
+    pub fn new(base_color: Color) -> Principled {
+        Principled {
+            base_color: Arc::new(SolidColor::new(base_color)),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            subsurface: constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    fn lobes(&self, r_in: &Ray, rec: &HitRecord) -> PrincipledLobes {
+        let scalar = |t: &Arc<dyn Texture>| scalar_value(t, rec);
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = (1.0 - metallic) * scalar(&self.transmission);
+
+        let lum = luminance(base_color);
+        let tint = if lum > 0.0 {
+            base_color / lum
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        };
+        let white = Color::new(1.0, 1.0, 1.0);
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_spec =
+            scalar(&self.specular) * 0.08 * ((1.0 - specular_tint) * white + specular_tint * tint);
+        let spec0 = (1.0 - metallic) * dielectric_spec + metallic * base_color;
+        let sheen_tint = scalar(&self.sheen_tint);
+        let sheen = scalar(&self.sheen) * ((1.0 - sheen_tint) * white + sheen_tint * tint);
+        let clearcoat = scalar(&self.clearcoat);
+        let gloss = scalar(&self.clearcoat_gloss);
+
+        let mut uvw = Onb::default();
+        uvw.build_from_w(rec.normal);
+        let wo = uvw.to_local(-unit_vector(r_in.direction()));
+        let cos_o = wo.z().max(0.0);
+
+        // Pick lobes roughly by how much each reflects towards wo.
+        let w_diffuse = (1.0 - metallic) * (1.0 - transmission);
+        let w_specular = (1.0 - transmission) * luminance(schlick(spec0, cos_o)) + transmission;
+        let w_clearcoat = 0.25 * clearcoat * (0.04 + 0.96 * schlick_weight(cos_o));
+        let total = w_diffuse + w_specular + w_clearcoat;
+        let (p_diffuse, p_specular) = if total > 0.0 {
+            (w_diffuse / total, w_specular / total)
+        } else {
+            (0.0, 1.0)
+        };
+
+        PrincipledLobes {
+            uvw,
+            wo,
+            eta: if rec.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+            base_color,
+            metallic,
+            roughness,
+            spec0,
+            sheen,
+            transmission,
+            subsurface: scalar(&self.subsurface),
+            clearcoat,
+            ggx: Ggx::from_roughness(roughness),
+            clearcoat_alpha: (1.0 - gloss) * 0.1 + gloss * 0.001,
+            p_diffuse,
+            p_specular,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        rng: &mut RandGen,
+    ) -> (bool, Option<Box<dyn Pdf>>) {
+        srec.attenuation = Color::new(1.0, 1.0, 1.0);
+        let pdf = PrincipledPdf {
+            lobes: self.lobes(r_in, rec),
+            select: random_double(rng),
+        };
+        (true, Some(Box::new(pdf)))
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let lobes = self.lobes(r_in, rec);
+        lobes.pdf(lobes.uvw.to_local(unit_vector(scattered.direction())))
+    }
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        let lobes = self.lobes(r_in, rec);
+        lobes.eval(lobes.uvw.to_local(unit_vector(scattered.direction())))
+    }
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - clamp(cosine, 0.0, 1.0)).powi(5)
+}
+
+fn schlick(r0: Color, cosine: f64) -> Color {
+    let w = schlick_weight(cosine);
+    (1.0 - w) * r0 + w * Color::new(1.0, 1.0, 1.0)
+}
+
+// Burley's GTR1, the clear coat's microfacet distribution.
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+// A principled material's parameters at one hit, in the shading frame, where
+// wo is always above the surface.
+#[derive(Clone, Copy)]
+struct PrincipledLobes {
+    uvw: Onb,
+    wo: Vec3,
+    eta: f64,
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    spec0: Color,
+    sheen: Color,
+    transmission: f64,
+    subsurface: f64,
+    clearcoat: f64,
+    ggx: Ggx,
+    clearcoat_alpha: f64,
+    p_diffuse: f64,
+    p_specular: f64,
+}
+
+impl PrincipledLobes {
+    // Chance that the specular lobe reflects off a facet rather than
+    // refracting through it.
+    fn reflect_chance(&self, wm: Vec3) -> f64 {
+        let t = self.transmission;
+        (1.0 - t) + t * fr_dielectric(dot(self.wo, wm), self.eta)
+    }
+
+    // BSDF times |cos| for a direction in the shading frame.
+    fn eval(&self, wi: Vec3) -> Color {
+        let wo = self.wo;
+        let cos_o = wo.z();
+        let cos_i = wi.z();
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        if cos_i < 0.0 {
+            if self.transmission <= 0.0 {
+                return Color::new(0.0, 0.0, 0.0);
+            }
+            let ft = self.ggx.transmission(wo, wi, self.eta);
+            let tint = Color::new(
+                self.base_color.x().sqrt(),
+                self.base_color.y().sqrt(),
+                self.base_color.z().sqrt(),
+            );
+            return self.transmission * ft * tint;
+        }
+
+        let wh = wo + wi;
+        if wh.length_squared() == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let wh = unit_vector(wh);
+        let cos_d = dot(wi, wh);
+
+        let mut f = Color::new(0.0, 0.0, 0.0);
+
+        let diffuse_weight = (1.0 - self.metallic) * (1.0 - self.transmission);
+        if diffuse_weight > 0.0 {
+            let fo = schlick_weight(cos_o);
+            let fi = schlick_weight(cos_i);
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fd = (1.0 + (fd90 - 1.0) * fo) * (1.0 + (fd90 - 1.0) * fi);
+            // Hanrahan-Krueger-like flattening in place of real subsurface
+            // scattering.
+            let fss90 = self.roughness * cos_d * cos_d;
+            let fss = (1.0 + (fss90 - 1.0) * fo) * (1.0 + (fss90 - 1.0) * fi);
+            let ss = 1.25 * (fss * (1.0 / (cos_o + cos_i) - 0.5) + 0.5);
+            let diffuse = ((1.0 - self.subsurface) * fd + self.subsurface * ss) / PI;
+            let sheen = schlick_weight(cos_d) * self.sheen;
+            f += diffuse_weight * (diffuse * self.base_color + sheen);
+        }
+
+        let t = self.transmission;
+        let fresnel = (1.0 - t) * schlick(self.spec0, cos_d)
+            + t * fr_dielectric(cos_d, self.eta) * Color::new(1.0, 1.0, 1.0);
+        f += self.ggx.d(wh) * self.ggx.g(wo, wi) / (4.0 * cos_o * cos_i) * fresnel;
+
+        if self.clearcoat > 0.0 {
+            let fr = 0.04 + 0.96 * schlick_weight(cos_d);
+            let g = Ggx::new(0.25, 0.25);
+            let coat = 0.25
+                * self.clearcoat
+                * gtr1(wh.z(), self.clearcoat_alpha)
+                * fr
+                * g.g1(wo)
+                * g.g1(wi)
+                / (4.0 * cos_o * cos_i);
+            f += coat * Color::new(1.0, 1.0, 1.0);
+        }
+
+        cos_i * f
+    }
+
+    // Density of sample() generating wi, summed over every lobe that can.
+    fn pdf(&self, wi: Vec3) -> f64 {
+        let wo = self.wo;
+        let p_clearcoat = 1.0 - self.p_diffuse - self.p_specular;
+        let mut pdf = 0.0;
+
+        if wi.z() > 0.0 {
+            pdf += self.p_diffuse * wi.z() / PI;
+        }
+
+        let wh = wo + wi;
+        if wh.length_squared() > 0.0 {
+            let wh = unit_vector(wh);
+            let cos_d = dot(wo, wh);
+            if wh.z() > 0.0 && cos_d > 0.0 {
+                pdf += self.p_specular * self.reflect_chance(wh) * self.ggx.pdf(wo, wh)
+                    / (4.0 * cos_d);
+                if p_clearcoat > 0.0 {
+                    pdf +=
+                        p_clearcoat * gtr1(wh.z(), self.clearcoat_alpha) * wh.z() / (4.0 * cos_d);
+                }
+            }
+        }
+
+        if wi.z() < 0.0 && self.transmission > 0.0 {
+            if let Some(wm) = refraction_wm(wo, wi, self.eta) {
+                pdf += self.p_specular
+                    * (1.0 - self.reflect_chance(wm))
+                    * self.ggx.pdf(wo, wm)
+                    * refraction_jacobian(wo, wi, wm, self.eta);
+            }
+        }
+
+        pdf
+    }
+
+    fn sample(&self, u: (f64, f64), select: f64) -> Vec3 {
+        let wo = self.wo;
+        if u.0 < self.p_diffuse {
+            return random_cosine_direction((u.0 / self.p_diffuse, u.1));
+        }
+        if u.0 < self.p_diffuse + self.p_specular {
+            let u0 = ((u.0 - self.p_diffuse) / self.p_specular).min(1.0 - 1e-12);
+            let wm = self.ggx.sample_wm(wo, (u0, u.1));
+            if select >= self.reflect_chance(wm) {
+                if let Some(wi) = refract_wi(wo, wm, self.eta) {
+                    return wi;
+                }
+            }
+            return reflect_wi(wo, wm);
+        }
+        let p_clearcoat = 1.0 - self.p_diffuse - self.p_specular;
+        let u0 = ((u.0 - self.p_diffuse - self.p_specular) / p_clearcoat).min(1.0 - 1e-12);
+        let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+        let cos_h = ((1.0 - a2.powf(1.0 - u0)) / (1.0 - a2)).sqrt();
+        let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let wh = Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h);
+        reflect_wi(wo, wh)
+    }
+}
+
+pub struct PrincipledPdf {
+    lobes: PrincipledLobes,
+    // As in MicrofacetPdf.
+    select: f64,
+}
+impl Pdf for PrincipledPdf {
+    fn value(&self, direction: Vec3, _rng: &mut RandGen) -> f64 {
+        let lobes = &self.lobes;
+        lobes.pdf(lobes.uvw.to_local(unit_vector(direction)))
+    }
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        let lobes = &self.lobes;
+        lobes.uvw.local(lobes.sample(u, self.select))
+    }
+}
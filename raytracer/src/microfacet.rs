@@ -0,0 +1,205 @@
+use std::f64::consts::PI;
+
+use crate::vec3::*;
+
+// Directions here are in a shading frame with the normal along +z.
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Ggx {
+        Ggx {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    // Too sharp to sample reliably; better treated as a perfect mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    // Disney's perceptual mapping from roughness to alpha.
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        let alpha = roughness * roughness;
+        Ggx::new(alpha, alpha)
+    }
+
+    pub fn d(&self, wm: Vec3) -> f64 {
+        if wm.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let t = x * x + y * y + wm.z() * wm.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let x = w.x() * self.alpha_x;
+        let y = w.y() * self.alpha_y;
+        let alpha2_tan2 = (x * x + y * y) / cos2;
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height-correlated masking and shadowing.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals visible from wo, which is what sample_wm draws.
+    pub fn pdf(&self, wo: Vec3, wm: Vec3) -> f64 {
+        if wo.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / wo.z().abs() * self.d(wm) * dot(wo, wm).abs()
+    }
+
+    // Refraction from wo, above the surface, into wi below it through a
+    // dielectric of relative index eta, times |cos wi|. Radiance is
+    // compressed into the denser medium.
+    pub fn transmission(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        match refraction_wm(wo, wi, eta) {
+            Some(wm) => {
+                self.d(wm)
+                    * self.g(wo, wi)
+                    * (1.0 - fr_dielectric(dot(wo, wm), eta))
+                    * refraction_jacobian(wo, wi, wm, eta)
+                    * dot(wo, wm).abs()
+                    / (wo.z() * eta * eta)
+            }
+            None => 0.0,
+        }
+    }
+
+    // Heitz's sampling of the visible normals.
+    pub fn sample_wm(&self, wo: Vec3, u: (f64, f64)) -> Vec3 {
+        let mut wh = unit_vector(Vec3::new(
+            self.alpha_x * wo.x(),
+            self.alpha_y * wo.y(),
+            wo.z(),
+        ));
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {
+            unit_vector(cross(Vec3::new(0.0, 0.0, 1.0), wh))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(wh, t1);
+
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let mut p2 = r * phi.sin();
+        // Squash the disk onto the part of the hemisphere wo can see.
+        let s = 0.5 * (1.0 + wh.z());
+        p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+        let pz = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let nh = p1 * t1 + p2 * t2 + pz * wh;
+
+        unit_vector(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, where eta is the
+// index on the far side of the normal over the index on its near side.
+pub fn fr_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let mut cos_i = cos_i.clamp(-1.0, 1.0);
+    let mut eta = eta;
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+// Fresnel reflectance of a conductor with complex index eta + ik.
+pub fn fr_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+// Refracts wi, which points away from the surface, through the microfacet
+// normal n. None on total internal reflection.
+pub fn refract_wi(wi: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let mut cos_i = dot(n, wi);
+    let mut eta = eta;
+    let mut n = n;
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+        n = -n;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wi / eta + (cos_i / eta - cos_t) * n)
+}
+
+pub fn reflect_wi(wi: Vec3, n: Vec3) -> Vec3 {
+    -wi + 2.0 * dot(wi, n) * n
+}
+
+// Microfacet normal of a refraction from wo to wi, facing +z. None when the
+// pair can't come from a refraction through a single facet.
+pub fn refraction_wm(wo: Vec3, wi: Vec3, eta: f64) -> Option<Vec3> {
+    let etap = if wo.z() > 0.0 { eta } else { 1.0 / eta };
+    let wm = wi * etap + wo;
+    if wm.length_squared() == 0.0 {
+        return None;
+    }
+    let mut wm = unit_vector(wm);
+    if wm.z() < 0.0 {
+        wm = -wm;
+    }
+    if dot(wm, wi) * wi.z() < 0.0 || dot(wm, wo) * wo.z() < 0.0 {
+        return None;
+    }
+    Some(wm)
+}
+
+// Jacobian of wm with respect to wi for a refraction.
+pub fn refraction_jacobian(wo: Vec3, wi: Vec3, wm: Vec3, eta: f64) -> f64 {
+    let etap = if wo.z() > 0.0 { eta } else { 1.0 / eta };
+    let denom = dot(wi, wm) + dot(wo, wm) / etap;
+    dot(wi, wm).abs() / (denom * denom)
+}
@@ -1,6 +1,7 @@
 use crate::aabb::*;
 use crate::hittable::Hittable;
 use crate::material::Material;
+use crate::rtweekend::*;
 use crate::vec3::*;
 
 pub struct MovingSphere<M: Material> {
@@ -43,6 +44,7 @@ impl<M: Material> Hittable for MovingSphere<M> {
         t_min: f64,
         t_max: f64,
         rec: &mut crate::hittable::HitRecord,
+        _rng: &mut RandGen,
     ) -> Option<&dyn Material> {
         let oc = r.origin() - self.center(r.time());
         let a = r.direction().length_squared();
@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::hittable_list::*;
+use crate::material::*;
+use crate::scene_file::SceneError;
+use crate::texture::*;
+use crate::triangle::*;
+use crate::vec3::*;
+
+#[derive(Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub kd: Color,
+    pub ks: Color,
+    pub ke: Color,
+    pub ns: f64,
+    pub ni: f64,
+    pub d: f64,
+    pub illum: i32,
+    pub map_kd: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: String::from(name),
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::new(0.0, 0.0, 0.0),
+            ke: Color::new(0.0, 0.0, 0.0),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+
+    // Map the Phong-style description onto the closest material we have.
+    pub fn to_material(&self) -> Result<Arc<dyn Material>, SceneError> {
+        let albedo: Arc<dyn Texture> = match &self.map_kd {
+            Some(path) => match ImageTexture::load(path) {
+                Ok(texture) => Arc::new(texture),
+                Err(err) => {
+                    return Err(SceneError {
+                        path: path.clone(),
+                        line: None,
+                        column: None,
+                        message: format!("cannot load map_Kd of \"{}\": {}", self.name, err),
+                    })
+                }
+            },
+            None => Arc::new(SolidColor::new(self.kd)),
+        };
+
+        Ok(if !self.ke.near_zero() {
+            Arc::new(DiffuseLight::new(self.ke))
+        } else if self.d < 1.0 || self.illum == 4 || self.illum == 6 || self.illum == 7 {
+            Arc::new(Dielectric::new(self.ni))
+        } else if self.illum >= 3 && !self.ks.near_zero() {
+            // Blinn-Phong exponent to a roughness-like fuzz.
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt();
+            Arc::new(Metal::new(self.ks, fuzz))
+        } else {
+            Arc::new(Lambertian::mv(albedo))
+        })
+    }
+}
+
+fn error(path: &Path, line: usize, message: String) -> SceneError {
+    SceneError {
+        path: path.to_path_buf(),
+        line: Some(line),
+        column: None,
+        message,
+    }
+}
+
+fn parse_floats(path: &Path, line: usize, args: &[&str], n: usize) -> Result<Vec<f64>, SceneError> {
+    if args.len() < n {
+        return Err(error(path, line, format!("expected {} numbers", n)));
+    }
+    args.iter()
+        .take(n)
+        .map(|s| {
+            s.parse::<f64>()
+                .map_err(|_| error(path, line, format!("invalid number \"{}\"", s)))
+        })
+        .collect()
+}
+
+fn parse_color(path: &Path, line: usize, args: &[&str]) -> Result<Color, SceneError> {
+    let c = parse_floats(path, line, args, 3)?;
+    Ok(Color::new(c[0], c[1], c[2]))
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<Vec<MtlMaterial>, SceneError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|err| SceneError {
+        path: path.to_path_buf(),
+        line: None,
+        column: None,
+        message: err.to_string(),
+    })?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial::new(&args.join(" ")));
+            continue;
+        }
+        let current = match materials.last_mut() {
+            Some(current) => current,
+            None => {
+                return Err(error(
+                    path,
+                    line_no,
+                    format!("\"{}\" before newmtl", keyword),
+                ))
+            }
+        };
+        match keyword {
+            "Kd" => current.kd = parse_color(path, line_no, &args)?,
+            "Ks" => current.ks = parse_color(path, line_no, &args)?,
+            "Ke" => current.ke = parse_color(path, line_no, &args)?,
+            "Ns" => current.ns = parse_floats(path, line_no, &args, 1)?[0],
+            "Ni" => current.ni = parse_floats(path, line_no, &args, 1)?[0],
+            "d" => current.d = parse_floats(path, line_no, &args, 1)?[0],
+            "Tr" => current.d = 1.0 - parse_floats(path, line_no, &args, 1)?[0],
+            "illum" => current.illum = parse_floats(path, line_no, &args, 1)?[0] as i32,
+            // Options such as -s or -o come before the file name.
+            "map_Kd" => match args.last() {
+                Some(file) => current.map_kd = Some(dir.join(file)),
+                None => return Err(error(path, line_no, String::from("map_Kd needs a file"))),
+            },
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+#[derive(Copy, Clone)]
+struct ObjVertex {
+    position: usize,
+    texcoord: Option<usize>,
+    normal: Option<usize>,
+}
+
+// OBJ indices are 1-based and may be negative, counting back from the end.
+fn resolve_index(path: &Path, line: usize, s: &str, len: usize) -> Result<usize, SceneError> {
+    let i: i64 = s
+        .parse()
+        .map_err(|_| error(path, line, format!("invalid index \"{}\"", s)))?;
+    let index = if i < 0 { len as i64 + i } else { i - 1 };
+    if index < 0 || index >= len as i64 {
+        return Err(error(path, line, format!("index {} is out of range", i)));
+    }
+    Ok(index as usize)
+}
+
+#[derive(Default)]
+struct ObjGroup {
+    faces: Vec<[ObjVertex; 3]>,
+}
+
+// An OBJ file before it is turned into meshes.
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    texcoords: Vec<[f64; 2]>,
+    mtl: HashMap<String, MtlMaterial>,
+    // Faces grouped by (group name, material name), in file order.
+    groups: Vec<((String, String), ObjGroup)>,
+}
+
+fn parse_obj(path: &Path, source: &str) -> Result<ObjData, SceneError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut obj = ObjData::default();
+    let mut group_of: HashMap<(String, String), usize> = HashMap::new();
+    let mut group = String::from("default");
+    let mut usemtl = String::new();
+    // Index into obj.groups, looked up again only after g, o or usemtl.
+    let mut current: Option<usize> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let p = parse_floats(path, line_no, &args, 3)?;
+                obj.positions.push(Point3::new(p[0], p[1], p[2]));
+            }
+            "vn" => {
+                let n = parse_floats(path, line_no, &args, 3)?;
+                obj.normals.push(Vec3::new(n[0], n[1], n[2]));
+            }
+            "vt" => {
+                let t = parse_floats(path, line_no, &args, 1)?;
+                let v = match args.get(1) {
+                    Some(_) => parse_floats(path, line_no, &args, 2)?[1],
+                    None => 0.0,
+                };
+                obj.texcoords.push([t[0], v]);
+            }
+            "g" | "o" => {
+                group = if args.is_empty() {
+                    String::from("default")
+                } else {
+                    args.join(" ")
+                };
+                current = None;
+            }
+            "usemtl" => {
+                usemtl = args.join(" ");
+                current = None;
+            }
+            "mtllib" => {
+                for file in &args {
+                    for m in load_mtl(dir.join(file))? {
+                        obj.mtl.insert(m.name.clone(), m);
+                    }
+                }
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(path, line_no, String::from("face needs 3 vertices")));
+                }
+                let mut polygon = Vec::with_capacity(args.len());
+                for vertex in &args {
+                    let mut parts = vertex.split('/');
+                    let position =
+                        resolve_index(path, line_no, parts.next().unwrap(), obj.positions.len())?;
+                    let texcoord = match parts.next() {
+                        Some(s) if !s.is_empty() => {
+                            Some(resolve_index(path, line_no, s, obj.texcoords.len())?)
+                        }
+                        _ => None,
+                    };
+                    let normal = match parts.next() {
+                        Some(s) if !s.is_empty() => {
+                            Some(resolve_index(path, line_no, s, obj.normals.len())?)
+                        }
+                        _ => None,
+                    };
+                    polygon.push(ObjVertex {
+                        position,
+                        texcoord,
+                        normal,
+                    });
+                }
+
+                let g = match current {
+                    Some(g) => g,
+                    None => {
+                        let groups = &mut obj.groups;
+                        let g = *group_of
+                            .entry((group.clone(), usemtl.clone()))
+                            .or_insert_with_key(|key| {
+                                groups.push((key.clone(), ObjGroup::default()));
+                                groups.len() - 1
+                            });
+                        current = Some(g);
+                        g
+                    }
+                };
+                let faces = &mut obj.groups[g].1.faces;
+                // Fan triangulation, fine for the convex polygons modelling tools export.
+                for k in 1..polygon.len() - 1 {
+                    faces.push([polygon[0], polygon[k], polygon[k + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(obj)
+}
+
+pub struct ObjLoader {
+    // Overrides every MTL material when set.
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl ObjLoader {
+    pub fn new() -> ObjLoader {
+        ObjLoader { material: None }
+    }
+
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<HittableList, SceneError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|err| SceneError {
+            path: path.to_path_buf(),
+            line: None,
+            column: None,
+            message: err.to_string(),
+        })?;
+        let obj = parse_obj(path, &source)?;
+
+        let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        let mut list = HittableList::default();
+        for ((_, material_name), obj_group) in obj.groups {
+            let material = match &self.material {
+                Some(material) => material.clone(),
+                None => match materials.get(&material_name) {
+                    Some(material) => material.clone(),
+                    None => {
+                        let material = match obj.mtl.get(&material_name) {
+                            Some(m) => m.to_material()?,
+                            None => Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))),
+                        };
+                        materials.insert(material_name, material.clone());
+                        material
+                    }
+                },
+            };
+            list.add(Box::new(TriangleMesh::new(build_mesh(
+                &obj_group,
+                &obj.positions,
+                &obj.normals,
+                &obj.texcoords,
+                material,
+            ))));
+        }
+        Ok(list)
+    }
+}
+
+impl Default for ObjLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Copy only the vertices a group uses into its own buffers.
+fn build_mesh(
+    group: &ObjGroup,
+    positions: &[Point3],
+    normals: &[Vec3],
+    texcoords: &[[f64; 2]],
+    material: Arc<dyn Material>,
+) -> Mesh<Arc<dyn Material>> {
+    let mut mesh = Mesh::new(Vec::new(), Vec::new(), material);
+    let mut position_map = HashMap::new();
+    let mut normal_map = HashMap::new();
+    let mut texcoord_map = HashMap::new();
+
+    for face in &group.faces {
+        let mut vertices = [0; 3];
+        let mut face_normals = [0; 3];
+        let mut face_texcoords = [0; 3];
+        let mut has_normals = true;
+        let mut has_texcoords = true;
+
+        for (k, vertex) in face.iter().enumerate() {
+            vertices[k] = *position_map.entry(vertex.position).or_insert_with(|| {
+                mesh.positions.push(positions[vertex.position]);
+                mesh.positions.len() - 1
+            });
+            match vertex.normal {
+                Some(n) => {
+                    face_normals[k] = *normal_map.entry(n).or_insert_with(|| {
+                        mesh.normals.push(normals[n]);
+                        mesh.normals.len() - 1
+                    })
+                }
+                None => has_normals = false,
+            }
+            match vertex.texcoord {
+                Some(t) => {
+                    face_texcoords[k] = *texcoord_map.entry(t).or_insert_with(|| {
+                        mesh.texcoords.push(texcoords[t]);
+                        mesh.texcoords.len() - 1
+                    })
+                }
+                None => has_texcoords = false,
+            }
+        }
+
+        let mut mesh_face = MeshFace::new(vertices);
+        if has_normals {
+            mesh_face.normals = Some(face_normals);
+        }
+        if has_texcoords {
+            mesh_face.texcoords = Some(face_texcoords);
+        }
+        mesh.faces.push(mesh_face);
+    }
+    mesh
+}
+
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<HittableList, SceneError> {
+    ObjLoader::new().load(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_quad_with_materials() {
+        let dir = std::env::temp_dir().join(format!("raytracer-{}-obj", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("quad.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        let source = "mtllib quad.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl red
+f -4/-4/-1 -3/-3/-1 -2/-2/-1 -1/-1/-1
+g back
+usemtl missing
+f 3//1 2//1 1//1
+usemtl red
+f 1/1 3/3 4/4
+";
+        let obj = parse_obj(&dir.join("quad.obj"), source);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let obj = obj.unwrap();
+
+        let keys: Vec<(&str, &str)> = obj
+            .groups
+            .iter()
+            .map(|((g, m), _)| (g.as_str(), m.as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            [("default", "red"), ("back", "missing"), ("back", "red")]
+        );
+        assert_eq!(obj.mtl["red"].kd, Color::new(1.0, 0.0, 0.0));
+        assert!(!obj.mtl.contains_key("missing"));
+
+        let corners = |faces: &[[ObjVertex; 3]]| -> Vec<[usize; 3]> {
+            faces
+                .iter()
+                .map(|f| [f[0].position, f[1].position, f[2].position])
+                .collect()
+        };
+        // The quad is fanned from its first corner.
+        let quad = &obj.groups[0].1.faces;
+        assert_eq!(corners(quad), [[0, 1, 2], [0, 2, 3]]);
+        assert!(quad
+            .iter()
+            .flatten()
+            .all(|v| v.texcoord == Some(v.position) && v.normal == Some(0)));
+
+        let back = &obj.groups[1].1.faces;
+        assert_eq!(corners(back), [[2, 1, 0]]);
+        assert_eq!((back[0][0].texcoord, back[0][0].normal), (None, Some(0)));
+        let last = &obj.groups[2].1.faces;
+        assert_eq!(corners(last), [[0, 2, 3]]);
+        assert_eq!((last[0][1].texcoord, last[0][1].normal), (Some(2), None));
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let path = Path::new("bad.obj");
+        assert!(parse_obj(path, "v 0 0 0\nv 1 0 0\nf 1 2 3\n").is_err());
+        assert!(parse_obj(path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -4 1 2\n").is_err());
+        assert!(parse_obj(path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::vec3::*;
 
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct Onb {
     axis: [Vec3; 3],
 }
@@ -22,6 +22,10 @@ impl Onb {
     pub fn local(&self, a: Vec3) -> Vec3 {
         a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
     }
+    // Inverse of local.
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(dot(a, self.u()), dot(a, self.v()), dot(a, self.w()))
+    }
 
     pub fn build_from_w(&mut self, n: Vec3) {
         self.axis[2] = unit_vector(n);
@@ -6,8 +6,9 @@ use crate::rtweekend::*;
 use crate::vec3::*;
 
 pub trait Pdf {
-    fn value(&self, direction: Vec3) -> f64;
-    fn generate(&self) -> Vec3;
+    fn value(&self, direction: Vec3, rng: &mut RandGen) -> f64;
+    // Warps a uniform 2D sample to a direction.
+    fn generate(&self, u: (f64, f64)) -> Vec3;
 }
 
 #[derive(Default)]
@@ -22,7 +23,7 @@ impl CosinePdf {
     }
 }
 impl Pdf for CosinePdf {
-    fn value(&self, direction: Vec3) -> f64 {
+    fn value(&self, direction: Vec3, _rng: &mut RandGen) -> f64 {
         let cosine = dot(unit_vector(direction), self.uvw.w());
         if cosine <= 0.0 {
             0.0
@@ -30,8 +31,8 @@ impl Pdf for CosinePdf {
             cosine / PI
         }
     }
-    fn generate(&self) -> Vec3 {
-        self.uvw.local(random_cosine_direction())
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        self.uvw.local(random_cosine_direction(u))
     }
 }
 
@@ -45,31 +46,93 @@ impl<'a> HittablePdf<'a> {
     }
 }
 impl<'a> Pdf for HittablePdf<'a> {
-    fn generate(&self) -> Vec3 {
-        self.ptr.random(self.o)
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        self.ptr.random(self.o, u)
     }
-    fn value(&self, direction: Vec3) -> f64 {
-        self.ptr.pdf_value(self.o, direction)
+    fn value(&self, direction: Vec3, rng: &mut RandGen) -> f64 {
+        self.ptr.pdf_value(self.o, direction, rng)
     }
 }
 
+// Picks one of several pdfs with probability proportional to its weight.
 pub struct MixturePdf<'a> {
-    p: [&'a dyn Pdf; 2],
+    p: Vec<(f64, &'a dyn Pdf)>,
 }
 impl<'a> MixturePdf<'a> {
-    pub fn mv(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> MixturePdf<'a> {
-        MixturePdf { p: [p0, p1] }
+    // None unless some weight is positive and they add up to a finite total.
+    pub fn new(p: Vec<(f64, &'a dyn Pdf)>) -> Option<MixturePdf<'a>> {
+        let p: Vec<(f64, &'a dyn Pdf)> =
+            p.into_iter().filter(|(weight, _)| *weight > 0.0).collect();
+        let total: f64 = p.iter().map(|(weight, _)| weight).sum();
+        if !total.is_finite() || total <= 0.0 {
+            return None;
+        }
+        Some(MixturePdf {
+            p: p.into_iter()
+                .map(|(weight, p)| (weight / total, p))
+                .collect(),
+        })
     }
 }
 impl<'a> Pdf for MixturePdf<'a> {
-    fn value(&self, direction: Vec3) -> f64 {
-        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    fn value(&self, direction: Vec3, rng: &mut RandGen) -> f64 {
+        self.p
+            .iter()
+            .map(|(weight, p)| weight * p.value(direction, rng))
+            .sum()
     }
-    fn generate(&self) -> Vec3 {
-        if random_double() < 0.5 {
-            self.p[0].generate()
-        } else {
-            self.p[1].generate()
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        // The first coordinate picks a pdf and is then stretched back to [0,1).
+        // new() leaves at least one pdf, and the last one takes whatever
+        // rounding leaves over.
+        let last = self.p.len() - 1;
+        let mut start = 0.0;
+        let mut picked = last;
+        for (i, (weight, _)) in self.p[..last].iter().enumerate() {
+            if u.0 < start + weight {
+                picked = i;
+                break;
+            }
+            start += weight;
+        }
+        let (weight, p) = self.p[picked];
+        let u0 = ((u.0 - start) / weight).clamp(0.0, 1.0 - 1e-12);
+        p.generate((u0, u.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixture_rejects_degenerate_weights() {
+        let up = CosinePdf::new(Vec3::new(0.0, 1.0, 0.0));
+        assert!(MixturePdf::new(vec![]).is_none());
+        assert!(MixturePdf::new(vec![(0.0, &up as &dyn Pdf)]).is_none());
+        assert!(MixturePdf::new(vec![(-1.0, &up as &dyn Pdf)]).is_none());
+        assert!(MixturePdf::new(vec![(f64::NAN, &up as &dyn Pdf)]).is_none());
+        assert!(MixturePdf::new(vec![(f64::INFINITY, &up as &dyn Pdf)]).is_none());
+    }
+
+    #[test]
+    fn mixture_follows_its_weights() {
+        let up = CosinePdf::new(Vec3::new(0.0, 1.0, 0.0));
+        let down = CosinePdf::new(Vec3::new(0.0, -1.0, 0.0));
+        let mixture =
+            MixturePdf::new(vec![(3.0, &up as &dyn Pdf), (0.0, &up), (1.0, &down)]).unwrap();
+        let rng = &mut sample_rng(0, 0, 0);
+        assert!((mixture.value(Vec3::new(0.0, 1.0, 0.0), rng) - 0.75 / PI).abs() < 1e-12);
+        assert!((mixture.value(Vec3::new(0.0, -1.0, 0.0), rng) - 0.25 / PI).abs() < 1e-12);
+
+        let n = 400;
+        let mut upward = 0;
+        for i in 0..n {
+            let u = ((i as f64 + 0.5) / n as f64, random_double(rng));
+            if mixture.generate(u).y() > 0.0 {
+                upward += 1;
+            }
         }
+        assert_eq!(upward, 3 * n / 4);
     }
 }
@@ -11,19 +11,19 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    fn permute(p: &mut [usize; POINT_COUNT], n: usize) {
+    fn permute(p: &mut [usize; POINT_COUNT], n: usize, rng: &mut RandGen) {
         for i in (1..n).rev() {
-            let target = random_int(0, i as i32) as usize;
+            let target = random_int(0, i as i32, rng) as usize;
             p.swap(target, i);
         }
     }
 
-    fn perlin_generate_perm() -> [usize; POINT_COUNT] {
+    fn perlin_generate_perm(rng: &mut RandGen) -> [usize; POINT_COUNT] {
         let mut p: [usize; POINT_COUNT] = [0; POINT_COUNT];
         for (i, ptr) in p.iter_mut().enumerate().take(POINT_COUNT) {
             *ptr = i;
         }
-        Perlin::permute(&mut p, POINT_COUNT);
+        Perlin::permute(&mut p, POINT_COUNT, rng);
         p
     }
 
@@ -86,17 +86,17 @@ impl Perlin {
     }
 }
 
-impl Default for Perlin {
-    fn default() -> Self {
+impl Perlin {
+    pub fn new(rng: &mut RandGen) -> Perlin {
         let mut ranvec: [Vec3; POINT_COUNT] = [Vec3::default(); POINT_COUNT];
         for ptr in ranvec.iter_mut().take(POINT_COUNT) {
-            *ptr = unit_vector(Vec3::random_range(-1.0, 1.0));
+            *ptr = unit_vector(Vec3::random_range(-1.0, 1.0, rng));
         }
-        Self {
+        Perlin {
             ranvec,
-            perm_x: Perlin::perlin_generate_perm(),
-            perm_y: Perlin::perlin_generate_perm(),
-            perm_z: Perlin::perlin_generate_perm(),
+            perm_x: Perlin::perlin_generate_perm(rng),
+            perm_y: Perlin::perlin_generate_perm(rng),
+            perm_z: Perlin::perlin_generate_perm(rng),
         }
     }
 }
@@ -0,0 +1,472 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam::channel;
+use crossbeam::thread;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::camera::*;
+use crate::color::*;
+use crate::environment::*;
+use crate::filter::*;
+use crate::framebuffer::*;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::integrator::*;
+use crate::light::*;
+use crate::light_sampler::*;
+use crate::rtweekend::*;
+use crate::sampler::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+}
+
+// Running mean and variance of a pixel's luminance (Welford).
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PixelStats {
+    pub n: u32,
+    pub mean: f64,
+    pub m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    // Chan et al.'s combination of two partial estimates.
+    pub fn merge(&mut self, other: &PixelStats) {
+        if other.n == 0 {
+            return;
+        }
+        let n = self.n + other.n;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.n as f64 / n as f64;
+        self.m2 += other.m2 + delta * delta * self.n as f64 * other.n as f64 / n as f64;
+        self.n = n;
+    }
+
+    // Standard error of the mean, relative to the mean.
+    pub fn relative_error(&self) -> f64 {
+        if self.n < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.m2 / (self.n - 1) as f64;
+        let standard_error = (variance / self.n as f64).sqrt();
+        if self.mean.abs() < 1e-8 {
+            // A pixel that stays black has converged.
+            return if standard_error < 1e-8 {
+                0.0
+            } else {
+                f64::INFINITY
+            };
+        }
+        standard_error / self.mean.abs()
+    }
+}
+
+// Everything a progressive render has accumulated so far. Samples are drawn
+// from (seed, pixel, sample index) alone, so this is all it takes to carry on
+// from where a render stopped.
+#[derive(Debug, Clone)]
+pub struct RenderState {
+    pub film: Framebuffer,
+    pub stats: Vec<PixelStats>,
+    // Samples per pixel taken so far, converged pixels aside.
+    pub samples_done: i32,
+    pub passes: usize,
+    pub path_stats: PathStats,
+}
+
+impl RenderState {
+    pub fn new(width: u32, height: u32) -> RenderState {
+        RenderState {
+            film: Framebuffer::new(width, height),
+            stats: vec![PixelStats::default(); width as usize * height as usize],
+            samples_done: 0,
+            passes: 0,
+            path_stats: PathStats::default(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Renderer {
+    pub image_width: u32,
+    pub image_height: u32,
+    // Upper bound when sampling adaptively.
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    pub tile_size: u32,
+    pub thread_num: usize,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    // Samples added to every unconverged pixel per pass.
+    pub pass_samples: i32,
+    // Pixels are never considered converged below this many samples.
+    pub min_samples: i32,
+    // Relative standard error at which a pixel stops sampling; 0 disables
+    // adaptive sampling.
+    pub target_error: f64,
+    pub mis: MisHeuristic,
+    pub light_sampling: LightSampling,
+}
+
+impl Renderer {
+    pub fn new(
+        image_width: u32,
+        image_height: u32,
+        samples_per_pixel: i32,
+        max_depth: i32,
+    ) -> Renderer {
+        Renderer {
+            image_width,
+            image_height,
+            samples_per_pixel,
+            max_depth,
+            roulette_depth: 3,
+            diffuse_depth: None,
+            specular_depth: None,
+            transmission_depth: None,
+            tile_size: 32,
+            thread_num: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::default(),
+            pass_samples: 16,
+            min_samples: 16,
+            target_error: 0.0,
+            mis: MisHeuristic::Power,
+            light_sampling: LightSampling::Power,
+        }
+    }
+
+    pub fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        let mut y0 = 0;
+        while y0 < self.image_height {
+            let y1 = (y0 + self.tile_size).min(self.image_height);
+            let mut x0 = 0;
+            while x0 < self.image_width {
+                let x1 = (x0 + self.tile_size).min(self.image_width);
+                tiles.push(Tile { x0, y0, x1, y1 });
+                x0 = x1;
+            }
+            y0 = y1;
+        }
+        tiles
+    }
+
+    fn converged(&self, stats: &PixelStats) -> bool {
+        self.target_error > 0.0
+            && stats.n >= self.min_samples.max(2) as u32
+            && stats.relative_error() <= self.target_error
+    }
+
+    // Renders samples [first, first + count) of every active pixel of a tile.
+    #[allow(clippy::too_many_arguments)]
+    fn render_tile(
+        &self,
+        tile: &Tile,
+        first: i32,
+        count: i32,
+        active: &[bool],
+        sampler: &mut dyn Sampler,
+        cam: &Camera,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        punctual: &[PunctualLight],
+        background: &Background,
+        light_sampler: &LightSampler,
+    ) -> (FilmTile, Vec<PixelStats>, PathStats) {
+        let mut film = FilmTile::new(
+            tile.x0,
+            tile.y0,
+            tile.x1,
+            tile.y1,
+            self.image_width,
+            self.image_height,
+            self.filter,
+        );
+        let integrator = PathTracer {
+            world,
+            lights,
+            punctual,
+            background,
+            light_sampler,
+            max_depth: self.max_depth,
+            roulette_depth: self.roulette_depth,
+            diffuse_depth: self.diffuse_depth,
+            specular_depth: self.specular_depth,
+            transmission_depth: self.transmission_depth,
+            mis: self.mis,
+        };
+        let mut path_stats = PathStats::default();
+        let mut results = Vec::with_capacity((tile.width() * tile.height()) as usize);
+        for y in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                let pixel = (y * self.image_width + i) as u64;
+                let mut stats = PixelStats::default();
+                if active[pixel as usize] {
+                    for s in first..first + count {
+                        sampler.start_pixel_sample(i, y, s as u32);
+                        let rng = &mut sample_rng(self.seed, pixel, s as u64);
+                        let (du, dv) = sampler.get_2d();
+                        let fx = i as f64 + du;
+                        let fy = y as f64 + dv;
+                        // Image rows go top to bottom, the camera's t goes
+                        // bottom to top. A one pixel wide or tall image still
+                        // gets finite rays.
+                        let u = fx / (self.image_width - 1).max(1) as f64;
+                        let v =
+                            (self.image_height as f64 - fy) / (self.image_height - 1).max(1) as f64;
+                        let r = cam.get_ray(u, v, 0.0, 1.0, sampler);
+                        let color = integrator.ray_color(&r, sampler, rng, &mut path_stats);
+                        // A NaN or infinite sample would spread to every
+                        // pixel under the filter and keep this one from ever
+                        // converging, so it is dropped.
+                        if !(color.x().is_finite()
+                            && color.y().is_finite()
+                            && color.z().is_finite())
+                        {
+                            continue;
+                        }
+                        film.add_sample(fx, fy, color);
+                        stats.add(luminance(color));
+                    }
+                }
+                results.push(stats);
+            }
+        }
+        (film, results, path_stats)
+    }
+
+    pub fn render(
+        &self,
+        cam: &Camera,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        punctual: &[PunctualLight],
+        background: &Background,
+    ) -> Framebuffer {
+        let state = RenderState::new(self.image_width, self.image_height);
+        self.render_progressive(cam, world, lights, punctual, background, state, |_| {})
+            .film
+    }
+
+    // Carries on from `state` in passes of `pass_samples`, calling `on_pass`
+    // with the state after each one. Stopping after any pass and resuming from
+    // its state gives the same image as never stopping.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_progressive<F: FnMut(&RenderState)>(
+        &self,
+        cam: &Camera,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        punctual: &[PunctualLight],
+        background: &Background,
+        state: RenderState,
+        mut on_pass: F,
+    ) -> RenderState {
+        assert!(
+            state.film.width == self.image_width && state.film.height == self.image_height,
+            "Render state does not match the image size"
+        );
+        let mut state = state;
+        let tiles = self.tiles();
+        let light_sampler =
+            LightSampler::new(self.light_sampling, world, lights, punctual, background);
+        let sampler = self.sampler.build(self.samples_per_pixel, self.seed);
+
+        let total = state.stats.len() as u64 * self.samples_per_pixel.max(0) as u64;
+        let progress = if option_env!("CI").unwrap_or_default() == "true" {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(total)
+        };
+        progress.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {bar:40.cyan/blue} {percent}% ({eta}) {msg}"),
+        );
+        progress.set_position((state.stats.len() as u64 * state.samples_done as u64).min(total));
+
+        let pass_samples = self.pass_samples.max(1);
+        while state.samples_done < self.samples_per_pixel {
+            let done = state.samples_done;
+            let count = pass_samples.min(self.samples_per_pixel - done);
+            let active: Vec<bool> = state.stats.iter().map(|s| !self.converged(s)).collect();
+            let active_num = active.iter().filter(|&&a| a).count();
+            if active_num == 0 {
+                break;
+            }
+            progress.set_message(format!(
+                "pass {}, {} active pixels, path length {:.2}, {} threads",
+                state.passes + 1,
+                active_num,
+                state.path_stats.average_length(),
+                self.thread_num
+            ));
+
+            let next_tile = AtomicUsize::new(0);
+            let (tx, rx) = channel::unbounded();
+            // Tiles overlap once filtered, so they are summed in a fixed
+            // order to keep the result independent of the thread count.
+            let mut films: Vec<Option<FilmTile>> = tiles.iter().map(|_| None).collect();
+            let stats = &mut state.stats;
+            let path_stats = &mut state.path_stats;
+
+            thread::scope(|s| {
+                for _k in 0..self.thread_num.max(1) {
+                    let tx = tx.clone();
+                    let tiles = &tiles;
+                    let next_tile = &next_tile;
+                    let active = &active;
+                    let light_sampler = &light_sampler;
+                    let mut sampler = sampler.clone_box();
+                    s.spawn(move |_| loop {
+                        let id = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if id >= tiles.len() {
+                            break;
+                        }
+                        let results = self.render_tile(
+                            &tiles[id],
+                            done,
+                            count,
+                            active,
+                            &mut *sampler,
+                            cam,
+                            world,
+                            lights,
+                            punctual,
+                            background,
+                            light_sampler,
+                        );
+                        tx.send((id, results)).unwrap();
+                    });
+                }
+                drop(tx);
+
+                for (id, (tile_film, results, tile_paths)) in rx.iter() {
+                    let tile = &tiles[id];
+                    let mut result = results.iter();
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            stats[(y * self.image_width + x) as usize]
+                                .merge(result.next().unwrap());
+                        }
+                    }
+                    films[id] = Some(tile_film);
+                    path_stats.merge(&tile_paths);
+                    progress.inc(count as u64 * (tile.width() * tile.height()) as u64);
+                }
+            })
+            .unwrap();
+            for tile_film in films.iter().flatten() {
+                state.film.merge_tile(tile_film);
+            }
+
+            state.samples_done += count;
+            state.passes += 1;
+            on_pass(&state);
+        }
+        progress.finish();
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::*;
+
+    fn small_cornell_box() -> (Scene, Renderer) {
+        let mut scene = cornell_box();
+        scene.image_width = 20;
+        let mut renderer = scene.renderer();
+        renderer.samples_per_pixel = 8;
+        renderer.pass_samples = 2;
+        renderer.tile_size = 8;
+        (scene, renderer)
+    }
+
+    fn assert_same_film(a: &Framebuffer, b: &Framebuffer) {
+        assert_eq!((a.width, a.height), (b.width, b.height));
+        assert_eq!(a.sum, b.sum);
+        assert_eq!(a.weight, b.weight);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_image() {
+        let (scene, mut renderer) = small_cornell_box();
+        let mut render = |threads| {
+            renderer.thread_num = threads;
+            renderer.render(
+                &scene.camera,
+                &scene.world,
+                &scene.lights,
+                &scene.punctual_lights,
+                &scene.background,
+            )
+        };
+        let single = render(1);
+        assert_same_film(&single, &render(4));
+    }
+
+    #[test]
+    fn resuming_matches_a_straight_render() {
+        let (scene, mut renderer) = small_cornell_box();
+        renderer.thread_num = 2;
+        let render = |state: RenderState, on_pass: &mut dyn FnMut(&RenderState)| {
+            renderer.render_progressive(
+                &scene.camera,
+                &scene.world,
+                &scene.lights,
+                &scene.punctual_lights,
+                &scene.background,
+                state,
+                on_pass,
+            )
+        };
+        let empty = || RenderState::new(renderer.image_width, renderer.image_height);
+
+        let mut stopped = None;
+        let straight = render(empty(), &mut |state| {
+            if state.passes == 2 {
+                stopped = Some(state.clone());
+            }
+        });
+        let stopped = stopped.unwrap();
+        assert!(stopped.samples_done < straight.samples_done);
+
+        let resumed = render(stopped, &mut |_| {});
+        assert_same_film(&straight.film, &resumed.film);
+        assert_eq!(straight.stats, resumed.stats);
+        assert_eq!(straight.samples_done, resumed.samples_done);
+        assert_eq!(straight.passes, resumed.passes);
+        assert_eq!(straight.path_stats, resumed.path_stats);
+    }
+}
@@ -1,4 +1,14 @@
 use rand::prelude::*;
+use rand_pcg::Pcg32;
+
+// Every random decision draws from an explicit generator, so a render only
+// depends on its seed and not on how pixels are spread over threads.
+pub type RandGen = Pcg32;
+
+// For files that parse but make no sense.
+pub fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * std::f64::consts::PI / 180.0
@@ -14,20 +24,44 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     x
 }
 
-pub fn random_double() -> f64 {
+// f64::total_cmp, which the pinned toolchain predates: orders every value,
+// NaNs included, so sorting never panics.
+pub fn total_cmp(a: f64, b: f64) -> std::cmp::Ordering {
+    let key = |x: f64| {
+        let bits = x.to_bits() as i64;
+        bits ^ (((bits >> 63) as u64) >> 1) as i64
+    };
+    key(a).cmp(&key(b))
+}
+
+pub(crate) fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+pub fn seeded_rng(seed: u64) -> RandGen {
+    RandGen::seed_from_u64(seed)
+}
+
+// Generator for one sample of one pixel. Each pixel gets its own PCG
+// stream, so samples never depend on the order pixels are rendered in.
+pub fn sample_rng(seed: u64, pixel: u64, sample: u64) -> RandGen {
+    Pcg32::new(splitmix64(seed ^ splitmix64(sample)), pixel)
+}
+
+pub fn random_double(rng: &mut RandGen) -> f64 {
     // Returns a random real in [0,1).
-    let mut rng = rand::thread_rng();
     rng.gen_range(0.0..1.0)
 }
 
-pub fn random_double_range(min: f64, max: f64) -> f64 {
+pub fn random_double_range(min: f64, max: f64, rng: &mut RandGen) -> f64 {
     // Returns a random real in [min,max).
-    let mut rng = rand::thread_rng();
     rng.gen_range(min..max)
 }
 
-pub fn random_int(min: i32, max: i32) -> i32 {
+pub fn random_int(min: i32, max: i32, rng: &mut RandGen) -> i32 {
     // Returns a random integer in [min,max].
-    let mut rng = rand::thread_rng();
     rng.gen_range(min..=max)
 }
@@ -0,0 +1,518 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::rtweekend::*;
+
+// Largest double below one.
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Supplies the sample values of one pixel sample, one dimension at a time.
+// Every pixel sample asks for its dimensions in the same order: the pixel
+// position, the lens, the time, then one 2D sample per bounce.
+pub trait Sampler: Send + Sync {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+    fn clone_box(&self) -> Box<dyn Sampler>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn build(&self, samples_per_pixel: i32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue_noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!(
+                "unknown sampler \"{}\", expected independent, stratified, halton, sobol or blue_noise",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "blue_noise",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn hash(a: u64, b: u64) -> u64 {
+    splitmix64(a ^ splitmix64(b))
+}
+
+fn hash3(a: u64, b: u64, c: u64) -> u64 {
+    hash(hash(a, b), c)
+}
+
+fn pixel_key(x: u32, y: u32) -> u64 {
+    (y as u64) << 32 | x as u64
+}
+
+// Uniform double in [0,1) from the top 53 bits of a hash.
+fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+fn u32_to_unit(v: u32) -> f64 {
+    (v as f64 * (1.0 / 4294967296.0)).min(ONE_MINUS_EPSILON)
+}
+
+// Kensler's hash-based permutation of [0,l): element i under permutation p.
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+// Burley's nested uniform scramble, an Owen scramble of the bits of v.
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v = v.wrapping_add(seed);
+    v ^= v.wrapping_mul(0x6c50b47c);
+    v ^= v.wrapping_mul(0xb82f1e52);
+    v ^= v.wrapping_mul(0xc7afe638);
+    v ^= v.wrapping_mul(0x8d22f6e6);
+    v.reverse_bits()
+}
+
+// The first two dimensions of the Sobol sequence, which form a (0,2)-sequence.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut a = index;
+    let mut v = 1u32 << 31;
+    let mut y = 0;
+    while a != 0 {
+        if a & 1 != 0 {
+            y ^= v;
+        }
+        a >>= 1;
+        v ^= v >> 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+// Independent uniform randoms, the same as drawing from the sample's rng.
+#[derive(Clone)]
+pub struct IndependentSampler {
+    seed: u64,
+    rng: RandGen,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: seeded_rng(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = sample_rng(hash(self.seed, 1), pixel_key(x, y), index as u64);
+    }
+    fn get_1d(&mut self) -> f64 {
+        random_double(&mut self.rng)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (random_double(&mut self.rng), random_double(&mut self.rng))
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+// Jittered strata, shuffled per pixel and dimension. 1D dimensions use one
+// stratum per sample, 2D ones the largest square grid that fits. Once a pixel
+// has used up every stratum the next samples start over with a new shuffle.
+#[derive(Clone)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1) as u32,
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample(&self, strata: u32, jitter: u64) -> (u32, f64) {
+        let round = self.index / strata;
+        let key = hash3(self.seed, self.pixel, self.dimension << 32 | round as u64);
+        let stratum = permute(self.index % strata, strata, key as u32);
+        let h = hash3(key, self.index as u64, jitter);
+        (stratum, to_unit(h))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_key(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let (stratum, dx) = self.sample(n, 0);
+        self.dimension += 1;
+        ((stratum as f64 + dx) / n as f64).min(ONE_MINUS_EPSILON)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let m = (self.samples_per_pixel as f64).sqrt() as u32;
+        let (stratum, dx) = self.sample(m * m, 0);
+        let (_, dy) = self.sample(m * m, 1);
+        self.dimension += 1;
+        (
+            (((stratum % m) as f64 + dx) / m as f64).min(ONE_MINUS_EPSILON),
+            (((stratum / m) as f64 + dy) / m as f64).min(ONE_MINUS_EPSILON),
+        )
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// Owen-scrambled radical inverse of a in the given base: each digit goes
+// through a random permutation picked by the digits before it.
+fn scrambled_radical_inverse(base: u64, mut a: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut key = seed;
+    let mut value = 0.0;
+    // Scrambled digits stay nonzero past the last digit of a, so keep going
+    // until they no longer change the result.
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        inv_base_m *= inv_base;
+        value += permute(digit as u32, base as u32, key as u32) as f64 * inv_base_m;
+        key = splitmix64(key ^ digit);
+        a = next;
+    }
+    value.min(ONE_MINUS_EPSILON)
+}
+
+// Halton sequence over the samples of a pixel, with every pixel scrambled
+// differently. Dimensions past the prime table fall back to hashing.
+#[derive(Clone)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_key(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let key = hash3(self.seed, self.pixel, self.dimension as u64);
+        let value = match PRIMES.get(self.dimension) {
+            Some(&base) => scrambled_radical_inverse(base, self.index as u64, key),
+            None => to_unit(hash(key, self.index as u64)),
+        };
+        self.dimension += 1;
+        value
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let x = self.get_1d();
+        (x, self.get_1d())
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+// Owen-scrambled Sobol points, padded: every dimension (or pair) is its own
+// (0,2)-sequence with an independent scramble, and the sample order is
+// shuffled per pixel and dimension so the pairs do not correlate.
+#[derive(Clone)]
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn point(&mut self) -> (u64, u32, u32) {
+        let key = hash3(self.seed, self.pixel, self.dimension);
+        self.dimension += 1;
+        let index = owen_scramble(self.index, key as u32);
+        let (x, y) = sobol_2d(index);
+        (key, x, y)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_key(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let (key, x, _) = self.point();
+        u32_to_unit(owen_scramble(x, (key >> 32) as u32))
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (key, x, y) = self.point();
+        let key = splitmix64(key);
+        (
+            u32_to_unit(owen_scramble(x, key as u32)),
+            u32_to_unit(owen_scramble(y, (key >> 32) as u32)),
+        )
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+const TILE_SIZE: usize = 64;
+
+// A 64x64 blue noise tile built with Ulichney's void-and-cluster method.
+// Values are the ranks of the pixels, mapped to [0,1).
+fn blue_noise_tile() -> Vec<f64> {
+    let n = TILE_SIZE * TILE_SIZE;
+    let sigma = 1.5;
+    let wrap = |d: usize| d.min(TILE_SIZE - d) as f64;
+    let mut kernel = vec![0.0; n];
+    for dy in 0..TILE_SIZE {
+        for dx in 0..TILE_SIZE {
+            let r2 = wrap(dx) * wrap(dx) + wrap(dy) * wrap(dy);
+            kernel[dy * TILE_SIZE + dx] = (-r2 / (2.0 * sigma * sigma)).exp();
+        }
+    }
+    let splat = |energy: &mut [f64], p: usize, sign: f64| {
+        let (px, py) = (p % TILE_SIZE, p / TILE_SIZE);
+        for y in 0..TILE_SIZE {
+            let dy = (y + TILE_SIZE - py) % TILE_SIZE;
+            for x in 0..TILE_SIZE {
+                let dx = (x + TILE_SIZE - px) % TILE_SIZE;
+                energy[y * TILE_SIZE + x] += sign * kernel[dy * TILE_SIZE + dx];
+            }
+        }
+    };
+    // Densest minority pixel, or the emptiest majority one.
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&p| pattern[p])
+            .max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+            .unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&p| !pattern[p])
+            .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+            .unwrap()
+    };
+
+    let rng = &mut seeded_rng(0);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let ones = n / 10;
+    let mut placed = 0;
+    while placed < ones {
+        let p = random_int(0, n as i32 - 1, rng) as usize;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Move points from clusters to voids until the pattern settles.
+    for _ in 0..n {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    let mut removed = pattern.clone();
+    let mut removed_energy = energy.clone();
+    for r in (0..ones).rev() {
+        let cluster = tightest_cluster(&removed, &removed_energy);
+        removed[cluster] = false;
+        splat(&mut removed_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    // Past half full the tightest cluster of zeros is the largest void of
+    // ones, so filling voids covers both remaining phases.
+    for r in ones..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
+
+// The same Owen-scrambled Sobol points in every pixel, each pixel shifted by a
+// blue noise value (Cranley-Patterson rotation). The error left at low sample
+// counts is then spread as high frequency noise across neighbouring pixels.
+#[derive(Clone)]
+pub struct BlueNoiseSampler {
+    seed: u64,
+    tile: Arc<Vec<f64>>,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u64,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> BlueNoiseSampler {
+        BlueNoiseSampler {
+            seed,
+            tile: Arc::new(blue_noise_tile()),
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Every dimension reads the tile at its own toroidal offset.
+    fn shift(&self, key: u64) -> f64 {
+        let x = (self.x as usize + key as usize % TILE_SIZE) % TILE_SIZE;
+        let y = (self.y as usize + (key >> 32) as usize % TILE_SIZE) % TILE_SIZE;
+        self.tile[y * TILE_SIZE + x]
+    }
+
+    fn rotate(value: u32, shift: f64) -> f64 {
+        let v = u32_to_unit(value) + shift;
+        (v - v.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let key = hash(self.seed, self.dimension);
+        self.dimension += 1;
+        let (x, _) = sobol_2d(self.index);
+        BlueNoiseSampler::rotate(owen_scramble(x, key as u32), self.shift(splitmix64(key)))
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let key = hash(self.seed, self.dimension);
+        self.dimension += 1;
+        let (x, y) = sobol_2d(self.index);
+        let kx = splitmix64(key);
+        let ky = splitmix64(kx);
+        (
+            BlueNoiseSampler::rotate(owen_scramble(x, key as u32), self.shift(kx)),
+            BlueNoiseSampler::rotate(owen_scramble(y, (key >> 32) as u32), self.shift(ky)),
+        )
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,305 @@
+use crate::aarect::*;
+use crate::bvh::*;
+use crate::camera::*;
+use crate::constant_medium::*;
+use crate::cube::*;
+use crate::environment::*;
+use crate::filter::*;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::integrator::*;
+use crate::light::*;
+use crate::light_sampler::*;
+use crate::material::*;
+use crate::moving_shpere::*;
+use crate::renderer::*;
+use crate::rtweekend::*;
+use crate::sampler::*;
+use crate::sphere::*;
+use crate::texture::*;
+use crate::tonemap::*;
+use crate::vec3::*;
+
+pub struct Scene {
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub punctual_lights: Vec<PunctualLight>,
+    pub background: Background,
+    pub camera: Camera,
+    pub aspect_ratio: f64,
+    pub image_width: u32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub target_error: f64,
+    pub pass_samples: i32,
+    pub min_samples: i32,
+    pub mis: MisHeuristic,
+    pub light_sampling: LightSampling,
+    pub tone_map: ToneMapper,
+}
+
+impl Scene {
+    // A scene lit only by its emitters, against black, with default settings.
+    pub fn new(
+        world: HittableList,
+        lights: HittableList,
+        camera: Camera,
+        aspect_ratio: f64,
+        image_width: u32,
+        samples_per_pixel: i32,
+    ) -> Scene {
+        Scene {
+            world,
+            lights,
+            punctual_lights: Vec::new(),
+            background: Background::Color(Color::new(0.0, 0.0, 0.0)),
+            camera,
+            aspect_ratio,
+            image_width,
+            samples_per_pixel,
+            max_depth: 50,
+            roulette_depth: 3,
+            diffuse_depth: None,
+            specular_depth: None,
+            transmission_depth: None,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::default(),
+            target_error: 0.0,
+            pass_samples: 16,
+            min_samples: 16,
+            mis: MisHeuristic::Power,
+            light_sampling: LightSampling::Power,
+            tone_map: ToneMapper::default(),
+        }
+    }
+
+    pub fn image_height(&self) -> u32 {
+        ((self.image_width as f64) / self.aspect_ratio) as u32
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        let mut renderer = Renderer::new(
+            self.image_width,
+            self.image_height(),
+            self.samples_per_pixel,
+            self.max_depth,
+        );
+        renderer.roulette_depth = self.roulette_depth;
+        renderer.diffuse_depth = self.diffuse_depth;
+        renderer.specular_depth = self.specular_depth;
+        renderer.transmission_depth = self.transmission_depth;
+        renderer.seed = self.seed;
+        renderer.sampler = self.sampler;
+        renderer.filter = self.filter;
+        renderer.target_error = self.target_error;
+        renderer.pass_samples = self.pass_samples;
+        renderer.min_samples = self.min_samples;
+        renderer.mis = self.mis;
+        renderer.light_sampling = self.light_sampling;
+        renderer
+    }
+
+    pub fn builtin(name: &str) -> Option<Scene> {
+        match name {
+            "cornell_box" => Some(cornell_box()),
+            "final_scene" => Some(final_scene()),
+            _ => None,
+        }
+    }
+}
+
+pub fn cornell_box() -> Scene {
+    let mut objects = HittableList::default();
+
+    let red = Lambertian::new(Color::new(0.65, 0.05, 0.05));
+    let white = Lambertian::new(Color::new(0.73, 0.73, 0.73));
+    let green = Lambertian::new(Color::new(0.12, 0.45, 0.15));
+    let light = DiffuseLight::new(Color::new(15.0, 15.0, 15.0));
+
+    objects.add(Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    objects.add(Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    objects.add(Box::new(FlipFace::new(XZRect::new(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    ))));
+    objects.add(Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
+    objects.add(Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white)));
+    objects.add(Box::new(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
+
+    let aluminum = Metal::new(Color::new(0.8, 0.85, 0.88), 0.0);
+    let box1 = Cube::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        aluminum,
+    );
+    let box1 = RotateY::new(box1, 15.0);
+    let box1 = Translate::new(box1, Vec3::new(265.0, 0.0, 295.0));
+    objects.add(Box::new(box1));
+
+    let glass = Dielectric::new(1.5);
+    objects.add(Box::new(Sphere::new(
+        Point3::new(190.0, 90.0, 190.0),
+        90.0,
+        glass,
+    )));
+
+    let mut lights = HittableList::default();
+    lights.add(Box::new(XZRect::new(
+        213.0,
+        343.0,
+        227.0,
+        332.0,
+        554.0,
+        Empty::default(),
+    )));
+    lights.add(Box::new(Sphere::new(
+        Point3::new(190.0, 90.0, 190.0),
+        90.0,
+        Empty::default(),
+    )));
+
+    let aspect_ratio = 1.0;
+    let camera = Camera::new(
+        Point3::new(278.0, 278.0, -800.0),
+        Point3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+    );
+
+    Scene::new(objects, lights, camera, aspect_ratio, 600, 100)
+}
+
+pub fn final_scene() -> Scene {
+    // Fixed seed, so the scene itself is the same whatever the render seed.
+    let rng = &mut seeded_rng(0);
+
+    let mut boxes1 = HittableList::default();
+    let ground = Lambertian::new(Color::new(0.48, 0.83, 0.53));
+
+    let boxes_per_side = 20;
+    for i in 0..boxes_per_side {
+        for j in 0..boxes_per_side {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f64 * w;
+            let z0 = -1000.0 + j as f64 * w;
+            let y0 = 0.0;
+            let x1 = x0 + w;
+            let y1 = random_double_range(1.0, 101.0, rng);
+            let z1 = z0 + w;
+
+            boxes1.add(Box::new(Cube::new(
+                Point3::new(x0, y0, z0),
+                Point3::new(x1, y1, z1),
+                ground,
+            )));
+        }
+    }
+
+    let mut objects = HittableList::default();
+
+    objects.add(Box::new(BvhNode::new(&mut boxes1, 0.0, 1.0)));
+
+    let light = DiffuseLight::new(Color::new(7.0, 7.0, 7.0));
+    objects.add(Box::new(FlipFace::new(XZRect::new(
+        123.0, 423.0, 147.0, 412.0, 554.0, light,
+    ))));
+
+    let center1 = Point3::new(400.0, 400.0, 200.0);
+    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+    let moving_sphere_material = Lambertian::new(Color::new(0.7, 0.3, 0.1));
+    objects.add(Box::new(MovingSphere::new(
+        center1,
+        center2,
+        0.0,
+        1.0,
+        50.0,
+        moving_sphere_material,
+    )));
+
+    objects.add(Box::new(Sphere::new(
+        Point3::new(260.0, 150.0, 45.0),
+        50.0,
+        Dielectric::new(1.5),
+    )));
+    objects.add(Box::new(Sphere::new(
+        Point3::new(0.0, 150.0, 145.0),
+        50.0,
+        Metal::new(Color::new(0.8, 0.8, 0.9), 1.0),
+    )));
+
+    let boundary = Sphere::new(Point3::new(360.0, 150.0, 145.0), 70.0, Dielectric::new(1.5));
+    objects.add(Box::new(boundary));
+    objects.add(Box::new(ConstantMedium::new(
+        boundary,
+        0.2,
+        Color::new(0.2, 0.4, 0.9),
+    )));
+    let boundary = Sphere::new(Point3::new(0.0, 0.0, 0.0), 5000.0, Dielectric::new(1.5));
+    objects.add(Box::new(ConstantMedium::new(
+        boundary,
+        0.0001,
+        Color::new(1.0, 1.0, 1.0),
+    )));
+
+    let emat = Lambertian::mv(ImageTexture::new("input/earthmap.jpg"));
+    objects.add(Box::new(Sphere::new(
+        Point3::new(400.0, 200.0, 400.0),
+        100.0,
+        emat,
+    )));
+    let pertext = NoiseTexture::new(0.1, rng);
+    objects.add(Box::new(Sphere::new(
+        Point3::new(220.0, 280.0, 300.0),
+        80.0,
+        Lambertian::mv(pertext),
+    )));
+
+    let mut boxes2 = HittableList::default();
+    let white = Lambertian::new(Color::new(0.73, 0.73, 0.73));
+    let ns = 1000;
+    for _j in 0..ns {
+        boxes2.add(Box::new(Sphere::new(
+            Point3::random_range(0.0, 165.0, rng),
+            10.0,
+            white,
+        )));
+    }
+
+    objects.add(Box::new(Translate::new(
+        RotateY::new(BvhNode::new(&mut boxes2, 0.0, 1.0), 15.0),
+        Vec3::new(-100.0, 270.0, 395.0),
+    )));
+
+    let mut lights = HittableList::default();
+    lights.add(Box::new(XZRect::new(
+        123.0,
+        423.0,
+        147.0,
+        412.0,
+        554.0,
+        Empty::default(),
+    )));
+
+    let aspect_ratio = 1.0;
+    let camera = Camera::new(
+        Point3::new(478.0, 278.0, -600.0),
+        Point3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+    );
+
+    Scene::new(objects, lights, camera, aspect_ratio, 800, 1000)
+}
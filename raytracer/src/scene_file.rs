@@ -0,0 +1,1375 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::aarect::*;
+use crate::bvh::*;
+use crate::camera::*;
+use crate::color::*;
+use crate::constant_medium::*;
+use crate::cube::*;
+use crate::environment::*;
+use crate::filter::*;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::ies::*;
+use crate::light::*;
+use crate::mat4::*;
+use crate::material::*;
+use crate::moving_shpere::*;
+use crate::obj::*;
+use crate::rtweekend::*;
+use crate::scene::*;
+use crate::sky::*;
+use crate::sphere::*;
+use crate::texture::*;
+use crate::tonemap::*;
+use crate::triangle::*;
+use crate::vec3::*;
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+fn default_focus_dist() -> f64 {
+    10.0
+}
+fn default_time1() -> f64 {
+    1.0
+}
+fn default_aspect_ratio() -> f64 {
+    1.0
+}
+fn default_width() -> u32 {
+    600
+}
+fn default_ior() -> f64 {
+    1.5
+}
+fn default_samples_per_pixel() -> i32 {
+    100
+}
+fn default_roulette_depth() -> i32 {
+    3
+}
+fn default_max_depth() -> i32 {
+    50
+}
+fn default_pass_samples() -> i32 {
+    16
+}
+fn default_true() -> bool {
+    true
+}
+fn default_sampler() -> String {
+    String::from("sobol")
+}
+fn default_mis() -> String {
+    String::from("power")
+}
+fn default_light_sampling() -> String {
+    String::from("power")
+}
+fn default_filter() -> String {
+    String::from("box")
+}
+fn default_operator() -> String {
+    String::from("clamp")
+}
+fn default_white() -> f64 {
+    4.0
+}
+fn default_intensity() -> f64 {
+    1.0
+}
+fn default_turbidity() -> f64 {
+    3.0
+}
+fn default_ground_albedo() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDesc {
+    pub lookfrom: [f64; 3],
+    pub lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    pub vup: [f64; 3],
+    pub vfov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default = "default_focus_dist")]
+    pub focus_dist: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageDesc {
+    #[serde(default = "default_aspect_ratio")]
+    pub aspect_ratio: f64,
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: i32,
+    #[serde(default = "default_max_depth")]
+    pub max_depth: i32,
+    // Russian roulette may end paths that are at least this long.
+    #[serde(default = "default_roulette_depth")]
+    pub roulette_depth: i32,
+    // Limits on each kind of bounce, on top of max_depth.
+    #[serde(default)]
+    pub diffuse_depth: Option<i32>,
+    #[serde(default)]
+    pub specular_depth: Option<i32>,
+    #[serde(default)]
+    pub transmission_depth: Option<i32>,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default = "default_sampler")]
+    pub sampler: String,
+    // Relative error at which a pixel stops sampling, 0 samples every pixel
+    // samples_per_pixel times.
+    #[serde(default)]
+    pub target_error: f64,
+    #[serde(default = "default_pass_samples")]
+    pub pass_samples: i32,
+    #[serde(default = "default_pass_samples")]
+    pub min_samples: i32,
+    // How light samples and bounces are weighted against each other.
+    #[serde(default = "default_mis")]
+    pub mis: String,
+    // How shadow rays pick a light.
+    #[serde(default = "default_light_sampling")]
+    pub light_sampling: String,
+}
+
+impl Default for ImageDesc {
+    fn default() -> Self {
+        Self {
+            aspect_ratio: default_aspect_ratio(),
+            width: default_width(),
+            samples_per_pixel: default_samples_per_pixel(),
+            max_depth: default_max_depth(),
+            roulette_depth: default_roulette_depth(),
+            diffuse_depth: None,
+            specular_depth: None,
+            transmission_depth: None,
+            seed: 0,
+            sampler: default_sampler(),
+            target_error: 0.0,
+            pass_samples: default_pass_samples(),
+            min_samples: default_pass_samples(),
+            mis: default_mis(),
+            light_sampling: default_light_sampling(),
+        }
+    }
+}
+
+// Pixel reconstruction filter. Without a radius, the filter's own default.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterDesc {
+    #[serde(rename = "type", default = "default_filter")]
+    pub kind: String,
+    pub radius: Option<f64>,
+}
+
+impl Default for FilterDesc {
+    fn default() -> Self {
+        Self {
+            kind: default_filter(),
+            radius: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToneMapDesc {
+    #[serde(default = "default_operator")]
+    pub operator: String,
+    #[serde(default)]
+    pub exposure: f64,
+    #[serde(default = "default_white")]
+    pub white: f64,
+}
+
+impl Default for ToneMapDesc {
+    fn default() -> Self {
+        Self {
+            operator: default_operator(),
+            exposure: 0.0,
+            white: default_white(),
+        }
+    }
+}
+
+// Angles are in degrees. A spot light's cone angle is its half-angle.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PunctualLightDesc {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    Spot {
+        position: [f64; 3],
+        target: [f64; 3],
+        intensity: [f64; 3],
+        cone_angle: f64,
+        #[serde(default)]
+        falloff_angle: f64,
+    },
+    Distant {
+        // Toward the light.
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+        #[serde(default)]
+        angular_diameter: f64,
+    },
+}
+
+// A lat-long .hdr or .exr map lighting the scene from every direction.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentDesc {
+    pub path: String,
+    // Degrees about +y.
+    #[serde(default)]
+    pub rotation: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+}
+
+// Daylight from an analytic sky model, lit by a sun that is sampled like the
+// other lights.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SkyDesc {
+    // Toward the sun.
+    pub sun_direction: [f64; 3],
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    #[serde(default = "default_ground_albedo")]
+    pub ground_albedo: [f64; 3],
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid { color: [f64; 3] },
+    Checker { even: TextureRef, odd: TextureRef },
+    Noise { scale: f64 },
+    Image { path: String },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum TextureRef {
+    Scalar(f64),
+    Color([f64; 3]),
+    Named(String),
+    Inline(Box<TextureDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDesc {
+    Empty,
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        ir: f64,
+    },
+    // Emits emit, or the color of a black body at temperature kelvin with
+    // the given luminance. An IES profile or a goniometric map makes the
+    // emission depend on direction.
+    DiffuseLight {
+        #[serde(default)]
+        emit: Option<TextureRef>,
+        #[serde(default)]
+        temperature: Option<f64>,
+        // Only with temperature, 1 if left out.
+        #[serde(default)]
+        luminance: Option<f64>,
+        #[serde(default)]
+        two_sided: bool,
+        #[serde(default)]
+        ies: Option<String>,
+        #[serde(default)]
+        goniometric: Option<String>,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+    RoughConductor {
+        eta: [f64; 3],
+        k: [f64; 3],
+        roughness: TextureRef,
+    },
+    RoughDielectric {
+        ir: f64,
+        roughness: TextureRef,
+    },
+    Principled(Box<PrincipledDesc>),
+}
+
+// Parameters left out keep Principled::new's defaults.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrincipledDesc {
+    #[serde(default)]
+    pub base_color: Option<TextureRef>,
+    #[serde(default)]
+    pub metallic: Option<TextureRef>,
+    #[serde(default)]
+    pub roughness: Option<TextureRef>,
+    #[serde(default)]
+    pub specular: Option<TextureRef>,
+    #[serde(default)]
+    pub specular_tint: Option<TextureRef>,
+    #[serde(default)]
+    pub sheen: Option<TextureRef>,
+    #[serde(default)]
+    pub sheen_tint: Option<TextureRef>,
+    #[serde(default)]
+    pub clearcoat: Option<TextureRef>,
+    #[serde(default)]
+    pub clearcoat_gloss: Option<TextureRef>,
+    #[serde(default)]
+    pub transmission: Option<TextureRef>,
+    #[serde(default)]
+    pub subsurface: Option<TextureRef>,
+    #[serde(default = "default_ior")]
+    pub ior: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDesc>),
+}
+
+// One step of a `transform`; steps apply in the order they are listed.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformDesc {
+    Translate([f64; 3]),
+    Scale([f64; 3]),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate { axis: [f64; 3], angle: f64 },
+    Matrix([[f64; 4]; 4]),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<MaterialRef>,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+        radius: f64,
+        material: Option<MaterialRef>,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: Option<MaterialRef>,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<MaterialRef>,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<MaterialRef>,
+    },
+    Cube {
+        p0: [f64; 3],
+        p1: [f64; 3],
+        material: Option<MaterialRef>,
+    },
+    Triangle {
+        v0: [f64; 3],
+        v1: [f64; 3],
+        v2: [f64; 3],
+        material: Option<MaterialRef>,
+    },
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        #[serde(default)]
+        normals: Vec<[f64; 3]>,
+        indices: Vec<[usize; 3]>,
+        material: Option<MaterialRef>,
+    },
+    // Without a material the OBJ file's own MTL materials are used.
+    Obj {
+        path: String,
+        material: Option<MaterialRef>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: TextureRef,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    FlipFace {
+        object: Box<ObjectDesc>,
+    },
+    Transform {
+        steps: Vec<TransformDesc>,
+        object: Box<ObjectDesc>,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+    Bvh {
+        objects: Vec<ObjectDesc>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub image: ImageDesc,
+    #[serde(default)]
+    pub background: [f64; 3],
+    // Takes the place of the background color.
+    pub environment: Option<EnvironmentDesc>,
+    // Takes the place of the background color and adds the sun.
+    pub sky: Option<SkyDesc>,
+    #[serde(default = "default_true")]
+    pub bvh: bool,
+    #[serde(default)]
+    pub textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: HashMap<String, MaterialDesc>,
+    pub objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub lights: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub punctual_lights: Vec<PunctualLightDesc>,
+    #[serde(default)]
+    pub filter: FilterDesc,
+    #[serde(default)]
+    pub tone_map: ToneMapDesc,
+}
+
+fn vec3(e: [f64; 3]) -> Vec3 {
+    Vec3 { e }
+}
+
+struct Loader<'a> {
+    path: &'a Path,
+    source: &'a str,
+    desc: &'a SceneDesc,
+    textures: HashMap<&'a str, Arc<dyn Texture>>,
+    materials: HashMap<&'a str, Arc<dyn Material>>,
+    resolving: Vec<&'a str>,
+    // Only used to build procedural textures, independent of the render seed.
+    rng: RandGen,
+}
+
+impl<'a> Loader<'a> {
+    fn error(&self, line: Option<usize>, message: String) -> SceneError {
+        SceneError {
+            path: self.path.to_path_buf(),
+            line,
+            column: None,
+            message,
+        }
+    }
+
+    // Serde does not keep spans once the file is parsed, so semantic errors
+    // point at the first line mentioning the offending name.
+    fn line_of(&self, needle: &str) -> Option<usize> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        self.source
+            .lines()
+            .position(|line| {
+                line.match_indices(needle).any(|(i, _)| {
+                    let before = line[..i].chars().next_back();
+                    let after = line[i + needle.len()..].chars().next();
+                    !matches!(before, Some(c) if is_word(c))
+                        && !matches!(after, Some(c) if is_word(c))
+                })
+            })
+            .map(|i| i + 1)
+    }
+
+    fn texture(&mut self, name: &'a str) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+        let desc = match self.desc.textures.get(name) {
+            Some(desc) => desc,
+            None => {
+                return Err(self.error(self.line_of(name), format!("unknown texture \"{}\"", name)))
+            }
+        };
+        if self.resolving.contains(&name) {
+            return Err(self.error(
+                self.line_of(name),
+                format!("texture \"{}\" references itself", name),
+            ));
+        }
+        self.resolving.push(name);
+        let texture = self.build_texture(desc);
+        self.resolving.pop();
+
+        let texture = texture?;
+        self.textures.insert(name, texture.clone());
+        Ok(texture)
+    }
+
+    fn texture_ref(&mut self, r: &'a TextureRef) -> Result<Arc<dyn Texture>, SceneError> {
+        match r {
+            TextureRef::Scalar(x) => Ok(Arc::new(SolidColor::new(Color::new(*x, *x, *x)))),
+            TextureRef::Color(c) => Ok(Arc::new(SolidColor::new(vec3(*c)))),
+            TextureRef::Named(name) => self.texture(name),
+            TextureRef::Inline(desc) => self.build_texture(desc),
+        }
+    }
+
+    fn build_texture(&mut self, desc: &'a TextureDesc) -> Result<Arc<dyn Texture>, SceneError> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(vec3(*color))),
+            TextureDesc::Checker { even, odd } => {
+                let even = self.texture_ref(even)?;
+                let odd = self.texture_ref(odd)?;
+                Arc::new(CheckerTexture::mv(even, odd))
+            }
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new(*scale, &mut self.rng)),
+            TextureDesc::Image { path } => {
+                let file = match self.path.parent() {
+                    Some(dir) => dir.join(path),
+                    None => PathBuf::from(path),
+                };
+                match ImageTexture::load(&file) {
+                    Ok(texture) => Arc::new(texture),
+                    Err(err) => {
+                        return Err(self.error(
+                            self.line_of(path),
+                            format!("cannot load image \"{}\": {}", file.display(), err),
+                        ))
+                    }
+                }
+            }
+        })
+    }
+
+    fn material(&mut self, name: &'a str) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.clone());
+        }
+        let desc = match self.desc.materials.get(name) {
+            Some(desc) => desc,
+            None => {
+                return Err(self.error(self.line_of(name), format!("unknown material \"{}\"", name)))
+            }
+        };
+        let material = self.build_material(desc)?;
+        self.materials.insert(name, material.clone());
+        Ok(material)
+    }
+
+    fn material_ref(
+        &mut self,
+        r: &'a Option<MaterialRef>,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        match r {
+            None => Ok(Arc::new(Empty::default())),
+            Some(MaterialRef::Named(name)) => self.material(name),
+            Some(MaterialRef::Inline(desc)) => self.build_material(desc),
+        }
+    }
+
+    fn build_material(&mut self, desc: &'a MaterialDesc) -> Result<Arc<dyn Material>, SceneError> {
+        Ok(match desc {
+            MaterialDesc::Empty => Arc::new(Empty::default()),
+            MaterialDesc::Lambertian { albedo } => {
+                Arc::new(Lambertian::mv(self.texture_ref(albedo)?))
+            }
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(vec3(*albedo), *fuzz)),
+            MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+            MaterialDesc::DiffuseLight {
+                emit,
+                temperature,
+                luminance,
+                two_sided,
+                ies,
+                goniometric,
+            } => {
+                let emit = match (emit, temperature) {
+                    (Some(_), None) if luminance.is_some() => {
+                        return Err(self.error(
+                            self.line_of("luminance"),
+                            String::from("diffuse light luminance only goes with temperature"),
+                        ))
+                    }
+                    (Some(emit), None) => self.texture_ref(emit)?,
+                    (None, Some(temperature)) => {
+                        if *temperature <= 0.0 {
+                            return Err(self.error(
+                                self.line_of("temperature"),
+                                String::from("blackbody temperature must be positive"),
+                            ));
+                        }
+                        let luminance = luminance.unwrap_or(1.0);
+                        Arc::new(SolidColor::new(luminance * blackbody(*temperature)))
+                    }
+                    _ => {
+                        return Err(self.error(
+                            self.line_of("diffuse_light"),
+                            String::from("diffuse light needs either emit or temperature"),
+                        ))
+                    }
+                };
+                let light = DiffuseLight::mv(emit).with_two_sided(*two_sided);
+                let profile = match (ies, goniometric) {
+                    (Some(_), Some(_)) => {
+                        return Err(self.error(
+                            self.line_of("goniometric"),
+                            String::from("diffuse light can't have both ies and goniometric"),
+                        ))
+                    }
+                    (Some(path), None) => {
+                        let file = match self.path.parent() {
+                            Some(dir) => dir.join(path),
+                            None => PathBuf::from(path),
+                        };
+                        match IesProfile::load(&file) {
+                            Ok(profile) => Some(EmissionProfile::Ies(Arc::new(profile))),
+                            Err(err) => {
+                                return Err(self.error(
+                                    self.line_of(path),
+                                    format!(
+                                        "cannot load IES profile \"{}\": {}",
+                                        file.display(),
+                                        err
+                                    ),
+                                ))
+                            }
+                        }
+                    }
+                    (None, Some(path)) => {
+                        let file = match self.path.parent() {
+                            Some(dir) => dir.join(path),
+                            None => PathBuf::from(path),
+                        };
+                        match EnvironmentMap::load(&file, 0.0, 1.0) {
+                            Ok(map) => Some(EmissionProfile::Goniometric(Arc::new(map))),
+                            Err(err) => {
+                                return Err(self.error(
+                                    self.line_of(path),
+                                    format!(
+                                        "cannot load goniometric map \"{}\": {}",
+                                        file.display(),
+                                        err
+                                    ),
+                                ))
+                            }
+                        }
+                    }
+                    (None, None) => None,
+                };
+                match profile {
+                    Some(profile) => Arc::new(light.with_profile(profile)),
+                    None => Arc::new(light),
+                }
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                Arc::new(Isotropic::mv(self.texture_ref(albedo)?))
+            }
+            MaterialDesc::RoughConductor { eta, k, roughness } => Arc::new(RoughConductor::mv(
+                vec3(*eta),
+                vec3(*k),
+                self.texture_ref(roughness)?,
+            )),
+            MaterialDesc::RoughDielectric { ir, roughness } => {
+                Arc::new(RoughDielectric::mv(*ir, self.texture_ref(roughness)?))
+            }
+            MaterialDesc::Principled(p) => {
+                let mut m = Principled::new(Color::new(0.8, 0.8, 0.8));
+                m.ior = p.ior;
+                let params = [
+                    (&p.base_color, &mut m.base_color),
+                    (&p.metallic, &mut m.metallic),
+                    (&p.roughness, &mut m.roughness),
+                    (&p.specular, &mut m.specular),
+                    (&p.specular_tint, &mut m.specular_tint),
+                    (&p.sheen, &mut m.sheen),
+                    (&p.sheen_tint, &mut m.sheen_tint),
+                    (&p.clearcoat, &mut m.clearcoat),
+                    (&p.clearcoat_gloss, &mut m.clearcoat_gloss),
+                    (&p.transmission, &mut m.transmission),
+                    (&p.subsurface, &mut m.subsurface),
+                ];
+                for (desc, texture) in params {
+                    if let Some(desc) = desc {
+                        *texture = self.texture_ref(desc)?;
+                    }
+                }
+                Arc::new(m)
+            }
+        })
+    }
+
+    fn build_object(&mut self, desc: &'a ObjectDesc) -> Result<Box<dyn Hittable>, SceneError> {
+        Ok(match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::new(
+                vec3(*center),
+                *radius,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Box::new(MovingSphere::new(
+                vec3(*center0),
+                vec3(*center1),
+                *time0,
+                *time1,
+                *radius,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Box::new(XYRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(XZRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(YZRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::Cube { p0, p1, material } => Box::new(Cube::new(
+                vec3(*p0),
+                vec3(*p1),
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::Triangle {
+                v0,
+                v1,
+                v2,
+                material,
+            } => Box::new(Triangle::new(
+                vec3(*v0),
+                vec3(*v1),
+                vec3(*v2),
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::Mesh {
+                positions,
+                normals,
+                indices,
+                material,
+            } => {
+                let line = self.line_of("mesh");
+                if !normals.is_empty() && normals.len() != positions.len() {
+                    return Err(
+                        self.error(line, String::from("mesh needs one normal per position"))
+                    );
+                }
+                if indices.is_empty() {
+                    return Err(self.error(line, String::from("mesh has no faces")));
+                }
+                let mut faces = Vec::with_capacity(indices.len());
+                for face in indices {
+                    if face.iter().any(|&i| i >= positions.len()) {
+                        return Err(
+                            self.error(line, format!("mesh face {:?} is out of range", face))
+                        );
+                    }
+                    let mut mesh_face = MeshFace::new(*face);
+                    if !normals.is_empty() {
+                        mesh_face.normals = Some(*face);
+                    }
+                    faces.push(mesh_face);
+                }
+                let mut mesh = Mesh::new(
+                    positions.iter().map(|p| vec3(*p)).collect(),
+                    faces,
+                    self.material_ref(material)?,
+                );
+                mesh.normals = normals.iter().map(|n| vec3(*n)).collect();
+                Box::new(TriangleMesh::new(mesh))
+            }
+            ObjectDesc::Obj { path, material } => {
+                let mut obj = ObjLoader::new();
+                if material.is_some() {
+                    obj.material = Some(self.material_ref(material)?);
+                }
+                let file = match self.path.parent() {
+                    Some(dir) => dir.join(path),
+                    None => PathBuf::from(path),
+                };
+                let mut list = obj.load(&file)?;
+                if list.objects.is_empty() {
+                    return Err(self.error(
+                        self.line_of(path),
+                        format!("\"{}\" has no faces", file.display()),
+                    ));
+                }
+                Box::new(BvhNode::new(&mut list, 0.0, 1.0))
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => Box::new(ConstantMedium::mv(
+                self.build_object(boundary)?,
+                *density,
+                self.texture_ref(albedo)?,
+            )),
+            ObjectDesc::Translate { offset, object } => {
+                Box::new(Translate::new(self.build_object(object)?, vec3(*offset)))
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                Box::new(RotateY::new(self.build_object(object)?, *angle))
+            }
+            ObjectDesc::FlipFace { object } => Box::new(FlipFace::new(self.build_object(object)?)),
+            ObjectDesc::Transform { steps, object } => {
+                let mut m = Mat4::identity();
+                for step in steps {
+                    let (name, values): (&str, Vec<f64>) = match step {
+                        TransformDesc::Translate(offset) => ("translate", offset.to_vec()),
+                        TransformDesc::Scale(s) => ("scale", s.to_vec()),
+                        TransformDesc::RotateX(angle) => ("rotate_x", vec![*angle]),
+                        TransformDesc::RotateY(angle) => ("rotate_y", vec![*angle]),
+                        TransformDesc::RotateZ(angle) => ("rotate_z", vec![*angle]),
+                        TransformDesc::Rotate { axis, angle } => (
+                            "rotate",
+                            axis.iter().chain([*angle].iter()).copied().collect(),
+                        ),
+                        TransformDesc::Matrix(rows) => {
+                            ("matrix", rows.iter().flatten().copied().collect())
+                        }
+                    };
+                    if values.iter().any(|x| !x.is_finite()) {
+                        return Err(
+                            self.error(self.line_of(name), format!("{} must be finite", name))
+                        );
+                    }
+                    m = match step {
+                        TransformDesc::Translate(offset) => Mat4::translate(vec3(*offset)),
+                        TransformDesc::Scale(s) => Mat4::scale(vec3(*s)),
+                        TransformDesc::RotateX(angle) => Mat4::rotate_x(*angle),
+                        TransformDesc::RotateY(angle) => Mat4::rotate_y(*angle),
+                        TransformDesc::RotateZ(angle) => Mat4::rotate_z(*angle),
+                        TransformDesc::Rotate { axis, angle } => Mat4::rotate(vec3(*axis), *angle),
+                        TransformDesc::Matrix(rows) => {
+                            // Points and directions would disagree under a
+                            // projective matrix.
+                            if rows[3] != [0.0, 0.0, 0.0, 1.0] {
+                                return Err(self.error(
+                                    self.line_of("matrix"),
+                                    String::from(
+                                        "matrix must be affine, with [0, 0, 0, 1] as its last row",
+                                    ),
+                                ));
+                            }
+                            Mat4::new(*rows)
+                        }
+                    } * m;
+                }
+                match Transform::new(self.build_object(object)?, m) {
+                    Some(transform) => Box::new(transform),
+                    None => {
+                        return Err(self.error(
+                            self.line_of("steps"),
+                            String::from("transform is not invertible"),
+                        ))
+                    }
+                }
+            }
+            ObjectDesc::List { objects } => Box::new(self.build_list(objects)?),
+            ObjectDesc::Bvh { objects } => {
+                let mut list = self.build_list(objects)?;
+                if list.objects.is_empty() {
+                    return Err(self.error(None, String::from("bvh needs at least one object")));
+                }
+                Box::new(BvhNode::new(&mut list, 0.0, 1.0))
+            }
+        })
+    }
+
+    fn build_list(&mut self, objects: &'a [ObjectDesc]) -> Result<HittableList, SceneError> {
+        let mut list = HittableList::default();
+        for object in objects {
+            list.add(self.build_object(object)?);
+        }
+        Ok(list)
+    }
+
+    fn punctual_light(&self, desc: &PunctualLightDesc) -> Result<PunctualLight, SceneError> {
+        Ok(match desc {
+            PunctualLightDesc::Point {
+                position,
+                intensity,
+            } => PunctualLight::point(vec3(*position), vec3(*intensity)),
+            PunctualLightDesc::Spot {
+                position,
+                target,
+                intensity,
+                cone_angle,
+                falloff_angle,
+            } => {
+                if position == target {
+                    return Err(self.error(
+                        self.line_of("target"),
+                        String::from("spot light target must differ from its position"),
+                    ));
+                }
+                if *cone_angle <= 0.0 || *cone_angle > 180.0 || *falloff_angle < 0.0 {
+                    return Err(self.error(
+                        self.line_of("cone_angle"),
+                        String::from("spot light cone angle must be in (0, 180] degrees"),
+                    ));
+                }
+                PunctualLight::spot(
+                    vec3(*position),
+                    vec3(*target),
+                    vec3(*intensity),
+                    *cone_angle,
+                    *falloff_angle,
+                )
+            }
+            PunctualLightDesc::Distant {
+                direction,
+                irradiance,
+                angular_diameter,
+            } => {
+                if *direction == [0.0; 3] {
+                    return Err(self.error(
+                        self.line_of("direction"),
+                        String::from("distant light direction must not be zero"),
+                    ));
+                }
+                if *angular_diameter < 0.0 || *angular_diameter >= 180.0 {
+                    return Err(self.error(
+                        self.line_of("angular_diameter"),
+                        String::from("distant light angular diameter must be in [0, 180) degrees"),
+                    ));
+                }
+                PunctualLight::distant(vec3(*direction), vec3(*irradiance), *angular_diameter)
+            }
+        })
+    }
+
+    fn sky(&self, desc: &SkyDesc) -> Result<Sky, SceneError> {
+        let sun_direction = vec3(desc.sun_direction);
+        if sun_direction.near_zero() || sun_direction.y() <= 0.0 {
+            return Err(self.error(
+                self.line_of("sun_direction"),
+                String::from("the sun must be above the horizon"),
+            ));
+        }
+        // The range the model was fitted over.
+        if desc.turbidity < 1.7 || desc.turbidity > 10.0 {
+            return Err(self.error(
+                self.line_of("turbidity"),
+                String::from("sky turbidity must be in [1.7, 10]"),
+            ));
+        }
+        Ok(Sky {
+            sun_direction,
+            turbidity: desc.turbidity,
+            ground_albedo: vec3(desc.ground_albedo),
+            intensity: desc.intensity,
+        })
+    }
+}
+
+fn syntax_error(path: &Path, line: usize, column: usize, message: String) -> SceneError {
+    // Both serde_json and serde_yaml append the location to their messages.
+    let suffix = format!(" at line {} column {}", line, column);
+    SceneError {
+        path: path.to_path_buf(),
+        line: Some(line),
+        column: Some(column),
+        message: message.trim_end_matches(&suffix).to_string(),
+    }
+}
+
+pub fn parse(path: &Path, source: &str) -> Result<SceneDesc, SceneError> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    match extension {
+        Some("json") => serde_json::from_str(source)
+            .map_err(|err| syntax_error(path, err.line(), err.column(), err.to_string())),
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(source).map_err(|err| match err.location() {
+                Some(loc) => syntax_error(path, loc.line(), loc.column(), err.to_string()),
+                None => SceneError {
+                    path: path.to_path_buf(),
+                    line: None,
+                    column: None,
+                    message: err.to_string(),
+                },
+            })
+        }
+        _ => Err(SceneError {
+            path: path.to_path_buf(),
+            line: None,
+            column: None,
+            message: String::from("unknown scene format, expected .json, .yaml or .yml"),
+        }),
+    }
+}
+
+pub fn build(path: &Path, source: &str, desc: &SceneDesc) -> Result<Scene, SceneError> {
+    let mut loader = Loader {
+        path,
+        source,
+        desc,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        resolving: Vec::new(),
+        rng: seeded_rng(0),
+    };
+
+    let mut world = loader.build_list(&desc.objects)?;
+    if desc.bvh && !world.objects.is_empty() {
+        let bvh = BvhNode::new(&mut world, 0.0, 1.0);
+        world = HittableList::default();
+        world.add(Box::new(bvh));
+    }
+    let lights = loader.build_list(&desc.lights)?;
+    let mut punctual_lights = Vec::with_capacity(desc.punctual_lights.len());
+    for light in &desc.punctual_lights {
+        punctual_lights.push(loader.punctual_light(light)?);
+    }
+    let background = match (&desc.environment, &desc.sky) {
+        (Some(_), Some(_)) => {
+            return Err(loader.error(
+                loader.line_of("sky"),
+                String::from("a scene can't have both an environment and a sky"),
+            ))
+        }
+        (None, Some(sky)) => {
+            let sky = loader.sky(sky)?;
+            punctual_lights.push(sky.sun_light());
+            Background::Environment(sky.environment())
+        }
+        (Some(env), None) => {
+            let file = match path.parent() {
+                Some(dir) => dir.join(&env.path),
+                None => PathBuf::from(&env.path),
+            };
+            match EnvironmentMap::load(&file, env.rotation, env.intensity) {
+                Ok(map) => Background::Environment(map),
+                Err(err) => {
+                    return Err(loader.error(
+                        loader.line_of(&env.path),
+                        format!("cannot load environment \"{}\": {}", file.display(), err),
+                    ))
+                }
+            }
+        }
+        (None, None) => Background::Color(vec3(desc.background)),
+    };
+
+    let sampler = match desc.image.sampler.parse() {
+        Ok(sampler) => sampler,
+        Err(message) => return Err(loader.error(loader.line_of(&desc.image.sampler), message)),
+    };
+    let mis = match desc.image.mis.parse() {
+        Ok(mis) => mis,
+        Err(message) => return Err(loader.error(loader.line_of(&desc.image.mis), message)),
+    };
+    let light_sampling = match desc.image.light_sampling.parse() {
+        Ok(light_sampling) => light_sampling,
+        Err(message) => {
+            return Err(loader.error(loader.line_of(&desc.image.light_sampling), message))
+        }
+    };
+    let mut filter = match desc.filter.kind.parse() {
+        Ok(kind) => Filter::new(kind),
+        Err(message) => return Err(loader.error(loader.line_of(&desc.filter.kind), message)),
+    };
+    if let Some(radius) = desc.filter.radius {
+        if radius <= 0.0 {
+            return Err(loader.error(
+                loader.line_of("filter"),
+                String::from("filter radius must be positive"),
+            ));
+        }
+        filter.radius = radius;
+    }
+    let mut tone_map = match desc.tone_map.operator.parse() {
+        Ok(operator) => ToneMapper::new(operator),
+        Err(message) => return Err(loader.error(loader.line_of(&desc.tone_map.operator), message)),
+    };
+    tone_map.exposure = desc.tone_map.exposure;
+    tone_map.white = desc.tone_map.white;
+
+    let image = &desc.image;
+    if !image.aspect_ratio.is_finite() || image.aspect_ratio <= 0.0 {
+        return Err(loader.error(
+            loader.line_of("aspect_ratio"),
+            String::from("aspect_ratio must be positive"),
+        ));
+    }
+    if image.width < 1 || (image.width as f64 / image.aspect_ratio) < 1.0 {
+        return Err(loader.error(
+            loader
+                .line_of("width")
+                .or_else(|| loader.line_of("aspect_ratio")),
+            String::from("the image must be at least one pixel wide and tall"),
+        ));
+    }
+    if image.samples_per_pixel < 1 {
+        return Err(loader.error(
+            loader.line_of("samples_per_pixel"),
+            String::from("samples_per_pixel must be at least 1"),
+        ));
+    }
+    let view = vec3(desc.camera.lookat) - vec3(desc.camera.lookfrom);
+    if view.length() <= 0.0 {
+        return Err(loader.error(
+            loader.line_of("lookat"),
+            String::from("lookfrom and lookat must differ"),
+        ));
+    }
+    // NaN too when vup is zero.
+    let sine = cross(unit_vector(view), unit_vector(vec3(desc.camera.vup))).length();
+    if sine.is_nan() || sine < 1e-9 {
+        return Err(loader.error(
+            loader.line_of("vup").or_else(|| loader.line_of("lookat")),
+            String::from("vup must not be parallel to the view direction"),
+        ));
+    }
+
+    let camera = Camera::new(
+        vec3(desc.camera.lookfrom),
+        vec3(desc.camera.lookat),
+        vec3(desc.camera.vup),
+        desc.camera.vfov,
+        desc.image.aspect_ratio,
+        desc.camera.aperture,
+        desc.camera.focus_dist,
+    );
+
+    let mut scene = Scene::new(
+        world,
+        lights,
+        camera,
+        image.aspect_ratio,
+        image.width,
+        image.samples_per_pixel,
+    );
+    scene.punctual_lights = punctual_lights;
+    scene.background = background;
+    scene.max_depth = image.max_depth;
+    scene.roulette_depth = image.roulette_depth;
+    scene.diffuse_depth = image.diffuse_depth;
+    scene.specular_depth = image.specular_depth;
+    scene.transmission_depth = image.transmission_depth;
+    scene.seed = image.seed;
+    scene.sampler = sampler;
+    scene.filter = filter;
+    scene.target_error = image.target_error;
+    scene.pass_samples = image.pass_samples;
+    scene.min_samples = image.min_samples;
+    scene.mis = mis;
+    scene.light_sampling = light_sampling;
+    scene.tone_map = tone_map;
+    Ok(scene)
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|err| SceneError {
+        path: path.to_path_buf(),
+        line: None,
+        column: None,
+        message: err.to_string(),
+    })?;
+    let desc = parse(path, &source)?;
+    build(path, &source, &desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "\
+camera: { lookfrom: [0, 1, 5], lookat: [0, 1, 0], vfov: 40 }
+image: { aspect_ratio: 2.0, width: 40, samples_per_pixel: 4 }
+materials:
+  white: { type: lambertian, albedo: [0.7, 0.7, 0.7] }
+  lamp: { type: diffuse_light, emit: [4, 4, 4] }
+objects:
+  - { type: sphere, center: [0, 1, 0], radius: 1, material: white }
+  - { type: xz_rect, x0: -1, x1: 1, z0: -1, z1: 1, k: 3, material: lamp }
+lights:
+  - { type: xz_rect, x0: -1, x1: 1, z0: -1, z1: 1, k: 3 }
+";
+
+    fn load_str(name: &str, source: &str) -> Result<Scene, SceneError> {
+        let path = Path::new(name);
+        build(path, source, &parse(path, source)?)
+    }
+
+    fn error_line(name: &str, source: &str) -> Option<usize> {
+        match load_str(name, source) {
+            Ok(_) => panic!("{} loaded", name),
+            Err(err) => err.line,
+        }
+    }
+
+    #[test]
+    fn loads_yaml() {
+        let scene = load_str("scene.yaml", YAML).unwrap();
+        assert_eq!(scene.image_width, 40);
+        assert_eq!(scene.image_height(), 20);
+        assert_eq!(scene.samples_per_pixel, 4);
+        assert_eq!(scene.lights.objects.len(), 1);
+    }
+
+    #[test]
+    fn loads_json() {
+        let source = r#"{
+            "camera": { "lookfrom": [0, 0, 5], "lookat": [0, 0, 0], "vfov": 30 },
+            "image": { "width": 16, "samples_per_pixel": 2 },
+            "background": [0.5, 0.7, 1.0],
+            "materials": { "red": { "type": "lambertian", "albedo": [0.8, 0.1, 0.1] } },
+            "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "red" }]
+        }"#;
+        let scene = load_str("scene.json", source).unwrap();
+        assert_eq!(scene.image_width, 16);
+        assert_eq!(scene.samples_per_pixel, 2);
+        assert_eq!(scene.world.objects.len(), 1);
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        assert_eq!(
+            error_line("scene.yaml", &YAML.replace("width: 40", "width: 0")),
+            Some(2)
+        );
+        assert_eq!(
+            error_line(
+                "scene.yaml",
+                &YAML.replace("lookat: [0, 1, 0]", "lookat: [0, 1, 5]"),
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            error_line(
+                "scene.yaml",
+                &YAML.replace("material: lamp", "material: lmap"),
+            ),
+            Some(8)
+        );
+        assert_eq!(error_line("scene.json", "{\n  \"camera\": 3\n}"), Some(2));
+    }
+}
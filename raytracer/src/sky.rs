@@ -0,0 +1,183 @@
+use std::f64::consts::PI;
+
+use crate::color::*;
+use crate::environment::*;
+use crate::light::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// Seen from the earth, the sun is half a degree across.
+const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+// Luminance of the sun's disk above the atmosphere, in kcd/m^2.
+const SUN_LUMINANCE: f64 = 1.88e6;
+
+const SKY_WIDTH: usize = 512;
+const SKY_HEIGHT: usize = 256;
+
+// Preetham, Shirley and Smits' analytic daylight, along with the sun it is
+// lit by. Radiance is in kcd/m^2, so a sunlit white wall is around 30; scale
+// it down with intensity or the exposure.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sky {
+    // Toward the sun, which must be above the horizon.
+    pub sun_direction: Vec3,
+    // Haziness, from about 2 for a clear sky to 10 for a hazy one.
+    pub turbidity: f64,
+    // Below the horizon there is a diffuse ground of this albedo.
+    pub ground_albedo: Color,
+    pub intensity: f64,
+}
+
+// Perez et al.'s sky luminance distribution, for one of Y, x or y.
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    // theta from the zenith, gamma from the sun.
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let cos_gamma = gamma.cos();
+        (1.0 + self.a * (self.b / cos_theta.max(1e-4)).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3) -> Sky {
+        Sky {
+            sun_direction,
+            turbidity: 3.0,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            intensity: 1.0,
+        }
+    }
+
+    fn sun(&self) -> Vec3 {
+        unit_vector(self.sun_direction)
+    }
+
+    // Sky radiance above the horizon, without the sun's disk.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let d = unit_vector(direction);
+        if d.y() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let t = self.turbidity;
+        let sun = self.sun();
+        let theta_s = sun.y().clamp(0.0, 1.0).acos();
+        let gamma = dot(d, sun).clamp(-1.0, 1.0).acos();
+
+        let perez_y = Perez {
+            a: 0.1787 * t - 1.4630,
+            b: -0.3554 * t + 0.4275,
+            c: -0.0227 * t + 5.3251,
+            d: 0.1206 * t - 2.5771,
+            e: -0.0670 * t + 0.3703,
+        };
+        let perez_x = Perez {
+            a: -0.0193 * t - 0.2592,
+            b: -0.0665 * t + 0.0008,
+            c: -0.0004 * t + 0.2125,
+            d: -0.0641 * t - 0.8989,
+            e: -0.0033 * t + 0.0452,
+        };
+        let perez_yc = Perez {
+            a: -0.0167 * t - 0.2608,
+            b: -0.0950 * t + 0.0092,
+            c: -0.0079 * t + 0.2102,
+            d: -0.0441 * t - 1.6537,
+            e: -0.0109 * t + 0.0529,
+        };
+
+        // Luminance and chromaticity at the zenith.
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (t1, t2, t3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * t1)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * t1 + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * t1 + 0.25886);
+        let zenith_yc = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * t1)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * t1 + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * t1 + 0.26688);
+
+        let relative = |p: &Perez| p.f(d.y(), gamma) / p.f(1.0, theta_s);
+        let lum = zenith_y * relative(&perez_y);
+        let x = zenith_x * relative(&perez_x);
+        let y = zenith_yc * relative(&perez_yc);
+        if lum <= 0.0 || y <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        xyz_to_rgb(x / y * lum, lum, (1.0 - x - y) / y * lum)
+    }
+
+    // Radiance of the sun's disk once through the atmosphere, which scatters
+    // blue light more the lower the sun is.
+    pub fn sun_radiance(&self) -> Color {
+        let cos_theta = self.sun().y();
+        if cos_theta <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        // Kasten and Young's relative air mass.
+        let zenith_deg = cos_theta.min(1.0).acos().to_degrees();
+        let m = 1.0 / (cos_theta + 0.50572 * (96.07995 - zenith_deg).powf(-1.6364));
+        // Angstrom's turbidity coefficient, as Preetham et al. relate it.
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * m).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * m).exp();
+            SUN_LUMINANCE * rayleigh * aerosol
+        };
+        // Wavelengths in micrometers standing for red, green and blue.
+        Color::new(
+            transmittance(0.65),
+            transmittance(0.55),
+            transmittance(0.45),
+        )
+    }
+
+    // The sun as a light, matched to the sky.
+    pub fn sun_light(&self) -> PunctualLight {
+        let cos_max = degrees_to_radians(SUN_ANGULAR_DIAMETER / 2.0).cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+        PunctualLight::distant(
+            self.sun(),
+            self.intensity * solid_angle * self.sun_radiance(),
+            SUN_ANGULAR_DIAMETER,
+        )
+    }
+
+    // The sky as an environment map. Below the horizon is the ground, lit by
+    // the sky and the sun.
+    pub fn environment(&self) -> EnvironmentMap {
+        let mut irradiance = Color::new(0.0, 0.0, 0.0);
+        let d_phi = 2.0 * PI / SKY_WIDTH as f64;
+        let d_theta = PI / SKY_HEIGHT as f64;
+        for y in 0..SKY_HEIGHT / 2 {
+            let theta = (y as f64 + 0.5) * d_theta;
+            for x in 0..SKY_WIDTH {
+                let phi = (x as f64 + 0.5) * d_phi;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                irradiance += self.radiance(d) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        let cos_max = degrees_to_radians(SUN_ANGULAR_DIAMETER / 2.0).cos();
+        irradiance += self.sun_radiance() * (2.0 * PI * (1.0 - cos_max) * self.sun().y());
+        let ground = self.ground_albedo * irradiance / PI;
+
+        EnvironmentMap::bake(SKY_WIDTH, SKY_HEIGHT, self.intensity, |d| {
+            if d.y() > 0.0 {
+                self.radiance(d)
+            } else {
+                ground
+            }
+        })
+    }
+}
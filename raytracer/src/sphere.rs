@@ -1,11 +1,11 @@
 use std::f64::consts::PI;
-use std::f64::INFINITY;
 
 use crate::aabb::*;
 use crate::hittable::*;
 use crate::material::*;
 use crate::onb::*;
 use crate::ray::*;
+use crate::rtweekend::*;
 use crate::vec3::*;
 
 #[derive(Copy, Clone)]
@@ -37,6 +37,7 @@ impl<M: Material> Hittable for Sphere<M> {
         t_min: f64,
         t_max: f64,
         rec: &mut HitRecord,
+        _rng: &mut RandGen,
     ) -> Option<&dyn Material> {
         let oc = r.origin() - self.center;
         let a = dot(r.direction(), r.direction());
@@ -74,26 +75,28 @@ impl<M: Material> Hittable for Sphere<M> {
         true
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
         let mut rec = HitRecord::default();
         if self
-            .hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec)
+            .hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY, &mut rec, rng)
             .is_none()
         {
             return 0.0;
         }
 
-        let cos_theta_max =
-            (1.0 - self.radius * self.radius / (self.center - o).length_squared()).sqrt();
+        // Clamped for points on the sphere itself, which see a hemisphere of it.
+        let cos_theta_max = (1.0 - self.radius * self.radius / (self.center - o).length_squared())
+            .max(0.0)
+            .sqrt();
         let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
         1.0 / solid_angle
     }
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
         let direction = self.center - o;
         let distance_squared = direction.length_squared();
         let mut uvw = Onb::default();
         uvw.build_from_w(direction);
-        uvw.local(random_to_sphere(self.radius, distance_squared))
+        uvw.local(random_to_sphere(self.radius, distance_squared, u))
     }
 }
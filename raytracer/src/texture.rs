@@ -1,4 +1,5 @@
 use image::*;
+use std::sync::Arc;
 
 use crate::color::*;
 use crate::perlin::*;
@@ -9,6 +10,12 @@ pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: Point3) -> Color;
 }
 
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        (**self).value(u, v, p)
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 pub struct SolidColor {
     color_value: Color,
@@ -35,7 +42,7 @@ impl<T0: Texture, T1: Texture> CheckerTexture<T0, T1> {
             odd: SolidColor::new(c2),
         }
     }
-    pub fn _mv(even: T0, odd: T1) -> CheckerTexture<T0, T1> {
+    pub fn mv(even: T0, odd: T1) -> CheckerTexture<T0, T1> {
         CheckerTexture { even, odd }
     }
 }
@@ -50,15 +57,14 @@ impl<T0: Texture, T1: Texture> Texture for CheckerTexture<T0, T1> {
     }
 }
 
-#[derive(Default)]
 pub struct NoiseTexture {
     noise: Perlin,
     scale: f64,
 }
 impl NoiseTexture {
-    pub fn new(scale: f64) -> NoiseTexture {
+    pub fn new(scale: f64, rng: &mut RandGen) -> NoiseTexture {
         NoiseTexture {
-            noise: Perlin::default(),
+            noise: Perlin::new(rng),
             scale,
         }
     }
@@ -78,11 +84,14 @@ pub struct ImageTexture {
 }
 impl ImageTexture {
     pub fn new(filename: &str) -> ImageTexture {
-        let dynamic_img = open(filename).unwrap();
+        ImageTexture::load(filename).unwrap()
+    }
+    pub fn load<P: AsRef<std::path::Path>>(filename: P) -> ImageResult<ImageTexture> {
+        let dynamic_img = open(filename)?;
         let (width, height) = dynamic_img.dimensions();
         let img = dynamic_img.into_rgb8();
 
-        ImageTexture { img, width, height }
+        Ok(ImageTexture { img, width, height })
     }
 }
 impl Texture for ImageTexture {
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::color::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operator {
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Aces,
+}
+
+impl FromStr for Operator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" | "none" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "reinhard_extended" => Ok(Operator::ReinhardExtended),
+            "aces" => Ok(Operator::Aces),
+            _ => Err(format!(
+                "unknown tone mapping operator \"{}\", expected clamp, reinhard, reinhard_extended or aces",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operator::Clamp => "clamp",
+            Operator::Reinhard => "reinhard",
+            Operator::ReinhardExtended => "reinhard_extended",
+            Operator::Aces => "aces",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Narkowicz's curve fit of the ACES filmic reference transform.
+fn aces(x: f64) -> f64 {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0)
+}
+
+// Exact piecewise sRGB transfer function, linear [0,1] in and out.
+pub fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Exposure, then a tone curve, then the sRGB encode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMapper {
+    pub operator: Operator,
+    // In stops: every +1 doubles the radiance.
+    pub exposure: f64,
+    // Luminance that extended Reinhard maps to pure white.
+    pub white: f64,
+}
+
+impl ToneMapper {
+    pub fn new(operator: Operator) -> ToneMapper {
+        ToneMapper {
+            operator,
+            exposure: 0.0,
+            white: 4.0,
+        }
+    }
+
+    // Scene-referred linear radiance to display-referred linear [0,1].
+    pub fn map(&self, c: Color) -> Color {
+        let c = c * 2f64.powf(self.exposure);
+        let mapped = match self.operator {
+            Operator::Clamp => c,
+            // Both Reinhard variants scale by luminance to keep hues intact.
+            Operator::Reinhard => {
+                let l = luminance(c);
+                if l <= 0.0 {
+                    return Color::default();
+                }
+                c * (1.0 / (1.0 + l))
+            }
+            Operator::ReinhardExtended => {
+                let l = luminance(c);
+                if l <= 0.0 {
+                    return Color::default();
+                }
+                let l_new = l * (1.0 + l / (self.white * self.white)) / (1.0 + l);
+                c * (l_new / l)
+            }
+            Operator::Aces => Color::new(aces(c.x()), aces(c.y()), aces(c.z())),
+        };
+        Color::new(
+            clamp(mapped.x(), 0.0, 1.0),
+            clamp(mapped.y(), 0.0, 1.0),
+            clamp(mapped.z(), 0.0, 1.0),
+        )
+    }
+
+    pub fn to_srgb8(&self, c: Color) -> [u8; 3] {
+        let c = self.map(c);
+        let quantize = |x: f64| (255.0 * srgb_encode(x) + 0.5) as u8;
+        [quantize(c.x()), quantize(c.y()), quantize(c.z())]
+    }
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper::new(Operator::Clamp)
+    }
+}
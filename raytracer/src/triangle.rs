@@ -0,0 +1,395 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// Möller–Trumbore: returns t and the barycentric coordinates of v1 and v2.
+fn intersect(v: [Point3; 3], r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let edge1 = v[1] - v[0];
+    let edge2 = v[2] - v[0];
+    let pvec = cross(r.direction(), edge2);
+    let det = dot(edge1, pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let tvec = r.origin() - v[0];
+    let b1 = dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = cross(tvec, edge1);
+    let b2 = dot(r.direction(), qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = dot(edge2, qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+fn interpolate(b: [f64; 3], a: [Vec3; 3]) -> Vec3 {
+    b[0] * a[0] + b[1] * a[1] + b[2] * a[2]
+}
+
+fn set_hit_record(
+    rec: &mut HitRecord,
+    r: &Ray,
+    hit: (f64, f64, f64),
+    v: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[[f64; 2]; 3]>,
+) {
+    let (t, b1, b2) = hit;
+    let b = [1.0 - b1 - b2, b1, b2];
+
+    rec.t = t;
+    rec.p = r.at(t);
+    match uvs {
+        Some(uv) => {
+            rec.u = b[0] * uv[0][0] + b[1] * uv[1][0] + b[2] * uv[2][0];
+            rec.v = b[0] * uv[0][1] + b[1] * uv[1][1] + b[2] * uv[2][1];
+        }
+        None => {
+            rec.u = b1;
+            rec.v = b2;
+        }
+    }
+
+    let outward_normal = unit_vector(cross(v[1] - v[0], v[2] - v[0]));
+    rec.set_face_normal(r, outward_normal);
+    if let Some(n) = normals {
+        // Shade with the interpolated normal, but keep it on the geometric side.
+        let mut shading_normal = unit_vector(interpolate(b, n));
+        if dot(shading_normal, outward_normal) < 0.0 {
+            shading_normal = -shading_normal;
+        }
+        rec.normal = if rec.front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+    }
+}
+
+fn triangle_box(v: [Point3; 3]) -> Aabb {
+    let mut min = v[0];
+    let mut max = v[0];
+    for p in &v[1..] {
+        for a in 0..3 {
+            min[a] = min[a].min(p[a]);
+            max[a] = max[a].max(p[a]);
+        }
+    }
+    // Pad axis-aligned triangles like the rects do.
+    for a in 0..3 {
+        if max[a] - min[a] < 0.0001 {
+            min[a] -= 0.0001;
+            max[a] += 0.0001;
+        }
+    }
+    Aabb::new(min, max)
+}
+
+fn triangle_area(v: [Point3; 3]) -> f64 {
+    0.5 * cross(v[1] - v[0], v[2] - v[0]).length()
+}
+
+fn random_on_triangle(v: [Point3; 3], u: (f64, f64)) -> Point3 {
+    let r1 = u.0.sqrt();
+    let r2 = u.1;
+    (1.0 - r1) * v[0] + (r1 * (1.0 - r2)) * v[1] + (r1 * r2) * v[2]
+}
+
+#[derive(Copy, Clone)]
+pub struct Triangle<M: Material> {
+    v: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[[f64; 2]; 3]>,
+    mat_ptr: M,
+}
+
+impl<M: Material> Triangle<M> {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, mat_ptr: M) -> Triangle<M> {
+        Triangle {
+            v: [v0, v1, v2],
+            normals: None,
+            uvs: None,
+            mat_ptr,
+        }
+    }
+    pub fn with_normals(mut self, n0: Vec3, n1: Vec3, n2: Vec3) -> Triangle<M> {
+        self.normals = Some([n0, n1, n2]);
+        self
+    }
+    pub fn with_uvs(mut self, uv0: [f64; 2], uv1: [f64; 2], uv2: [f64; 2]) -> Triangle<M> {
+        self.uvs = Some([uv0, uv1, uv2]);
+        self
+    }
+    pub fn area(&self) -> f64 {
+        triangle_area(self.v)
+    }
+}
+
+impl<M: Material> Hittable for Triangle<M> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        _rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        let hit = intersect(self.v, r, t_min, t_max)?;
+        set_hit_record(rec, r, hit, self.v, self.normals, self.uvs);
+        Some(&self.mat_ptr)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = triangle_box(self.v);
+        true
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        let mut rec = HitRecord::default();
+        if self
+            .hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY, &mut rec, rng)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let outward_normal = cross(self.v[1] - self.v[0], self.v[2] - self.v[0]);
+        let cosine = (dot(v, outward_normal) / (v.length() * outward_normal.length())).abs();
+
+        distance_squared / (cosine * self.area())
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        random_on_triangle(self.v, u) - o
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct MeshFace {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub texcoords: Option<[usize; 3]>,
+}
+
+impl MeshFace {
+    pub fn new(vertices: [usize; 3]) -> MeshFace {
+        MeshFace {
+            vertices,
+            normals: None,
+            texcoords: None,
+        }
+    }
+}
+
+// Vertex buffers shared by every triangle of a mesh.
+pub struct Mesh<M: Material> {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub texcoords: Vec<[f64; 2]>,
+    pub faces: Vec<MeshFace>,
+    pub mat_ptr: M,
+}
+
+impl<M: Material> Mesh<M> {
+    pub fn new(positions: Vec<Point3>, faces: Vec<MeshFace>, mat_ptr: M) -> Mesh<M> {
+        Mesh {
+            positions,
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            faces,
+            mat_ptr,
+        }
+    }
+
+    fn vertices(&self, face: usize) -> [Point3; 3] {
+        let f = &self.faces[face].vertices;
+        [
+            self.positions[f[0]],
+            self.positions[f[1]],
+            self.positions[f[2]],
+        ]
+    }
+    fn face_normals(&self, face: usize) -> Option<[Vec3; 3]> {
+        self.faces[face]
+            .normals
+            .map(|n| [self.normals[n[0]], self.normals[n[1]], self.normals[n[2]]])
+    }
+    fn face_uvs(&self, face: usize) -> Option<[[f64; 2]; 3]> {
+        self.faces[face].texcoords.map(|t| {
+            [
+                self.texcoords[t[0]],
+                self.texcoords[t[1]],
+                self.texcoords[t[2]],
+            ]
+        })
+    }
+}
+
+struct MeshTriangle<M: Material> {
+    mesh: Arc<Mesh<M>>,
+    face: usize,
+    // Ignores the shading normals, so hits carry the face's own normal.
+    flat: bool,
+}
+
+impl<M: Material> Hittable for MeshTriangle<M> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        _rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        let v = self.mesh.vertices(self.face);
+        let hit = intersect(v, r, t_min, t_max)?;
+        let normals = if self.flat {
+            None
+        } else {
+            self.mesh.face_normals(self.face)
+        };
+        set_hit_record(rec, r, hit, v, normals, self.mesh.face_uvs(self.face));
+        Some(&self.mesh.mat_ptr)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = triangle_box(self.mesh.vertices(self.face));
+        true
+    }
+}
+
+pub struct TriangleMesh<M: Material> {
+    mesh: Arc<Mesh<M>>,
+    bvh: BvhNode,
+    // The same faces without shading normals, for pdf_value. Only built when
+    // some face has them.
+    flat_bvh: Option<BvhNode>,
+    // Cumulative face areas, used to pick a face proportionally to its area.
+    area_cdf: Vec<f64>,
+}
+
+impl<M: 'static + Material> TriangleMesh<M> {
+    pub fn new(mesh: Mesh<M>) -> TriangleMesh<M> {
+        let mesh = Arc::new(mesh);
+
+        let triangles = |flat: bool| {
+            let mut list = HittableList::default();
+            for face in 0..mesh.faces.len() {
+                list.add(Box::new(MeshTriangle {
+                    mesh: mesh.clone(),
+                    face,
+                    flat,
+                }));
+            }
+            BvhNode::new(&mut list, 0.0, 1.0)
+        };
+        let bvh = triangles(false);
+        let flat_bvh = if mesh.faces.iter().any(|f| f.normals.is_some()) {
+            Some(triangles(true))
+        } else {
+            None
+        };
+
+        let mut area_cdf = Vec::with_capacity(mesh.faces.len());
+        let mut area = 0.0;
+        for face in 0..mesh.faces.len() {
+            area += triangle_area(mesh.vertices(face));
+            area_cdf.push(area);
+        }
+
+        TriangleMesh {
+            mesh,
+            bvh,
+            flat_bvh,
+            area_cdf,
+        }
+    }
+}
+
+impl<M: Material> TriangleMesh<M> {
+    pub fn area(&self) -> f64 {
+        *self.area_cdf.last().unwrap_or(&0.0)
+    }
+}
+
+impl<M: Material> Hittable for TriangleMesh<M> {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+        rng: &mut RandGen,
+    ) -> Option<&dyn Material> {
+        self.bvh.hit(r, t_min, t_max, rec, rng)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.bvh.bounding_box(time0, time1, output_box)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, rng: &mut RandGen) -> f64 {
+        // random() samples the whole surface by area, so every surface point
+        // along the direction contributes, not only the closest one. The
+        // cosine is to the face's own normal, whatever the shading normals.
+        let area = self.area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+        let bvh = self.flat_bvh.as_ref().unwrap_or(&self.bvh);
+        let r = Ray::new(o, v, 0.0);
+        let mut rec = HitRecord::default();
+        let mut t_min = 0.001;
+        let mut sum = 0.0;
+        while bvh.hit(&r, t_min, f64::INFINITY, &mut rec, rng).is_some() {
+            let distance_squared = rec.t * rec.t * v.length_squared();
+            let cosine = (dot(v, rec.normal) / v.length()).abs();
+            if cosine > 0.0 {
+                sum += distance_squared / (cosine * area);
+            }
+            t_min = rec.t + 0.0001;
+        }
+        sum
+    }
+    fn random(&self, o: Vec3, u: (f64, f64)) -> Vec3 {
+        if self.area_cdf.is_empty() {
+            // Any direction will do, pdf_value is zero for all of them.
+            return Vec3::new(0.0, 1.0, 0.0);
+        }
+        // The first coordinate picks a face by area and is then rescaled to
+        // where it fell inside that face's share.
+        let target = u.0 * self.area();
+        let face = self
+            .area_cdf
+            .partition_point(|&a| a < target)
+            .min(self.area_cdf.len() - 1);
+        let start = if face == 0 {
+            0.0
+        } else {
+            self.area_cdf[face - 1]
+        };
+        let share = self.area_cdf[face] - start;
+        let u0 = if share > 0.0 {
+            clamp((target - start) / share, 0.0, 1.0)
+        } else {
+            0.0
+        };
+        random_on_triangle(self.mesh.vertices(face), (u0, u.1)) - o
+    }
+}
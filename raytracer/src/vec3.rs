@@ -171,36 +171,32 @@ pub type Point3 = Vec3;
 pub type Color = Vec3;
 
 impl Vec3 {
-    pub fn random() -> Vec3 {
+    pub fn random(rng: &mut RandGen) -> Vec3 {
         Vec3 {
-            e: [random_double(), random_double(), random_double()],
+            e: [random_double(rng), random_double(rng), random_double(rng)],
         }
     }
-    pub fn random_range(min: f64, max: f64) -> Vec3 {
+    pub fn random_range(min: f64, max: f64, rng: &mut RandGen) -> Vec3 {
         Vec3 {
             e: [
-                random_double_range(min, max),
-                random_double_range(min, max),
-                random_double_range(min, max),
+                random_double_range(min, max, rng),
+                random_double_range(min, max, rng),
+                random_double_range(min, max, rng),
             ],
         }
     }
 }
-pub fn random_in_unit_sphere() -> Vec3 {
+pub fn random_in_unit_sphere(rng: &mut RandGen) -> Vec3 {
     let normal: Normal<f64> = Normal::new(0.0, 1.0).unwrap();
     let p = Vec3 {
-        e: [
-            normal.sample(&mut rand::thread_rng()),
-            normal.sample(&mut rand::thread_rng()),
-            normal.sample(&mut rand::thread_rng()),
-        ],
+        e: [normal.sample(rng), normal.sample(rng), normal.sample(rng)],
     };
     let p = unit_vector(p);
-    let u: f64 = random_double();
+    let u: f64 = random_double(rng);
     p * u.cbrt()
 }
-pub fn _random_in_hemisphere(normal: Vec3) -> Vec3 {
-    let in_unit_sphere = random_in_unit_sphere();
+pub fn _random_in_hemisphere(normal: Vec3, rng: &mut RandGen) -> Vec3 {
+    let in_unit_sphere = random_in_unit_sphere(rng);
     if dot(in_unit_sphere, normal) > 0.0 {
         in_unit_sphere
     } else {
@@ -208,9 +204,8 @@ pub fn _random_in_hemisphere(normal: Vec3) -> Vec3 {
     }
 }
 
-pub fn random_cosine_direction() -> Vec3 {
-    let r1 = random_double();
-    let r2 = random_double();
+pub fn random_cosine_direction(u: (f64, f64)) -> Vec3 {
+    let (r1, r2) = u;
     let z = (1.0 - r2).sqrt();
 
     let phi = 2.0 * PI * r1;
@@ -220,28 +215,29 @@ pub fn random_cosine_direction() -> Vec3 {
     Vec3::new(x, y, z)
 }
 
-pub fn random_unit_vector() -> Vec3 {
-    unit_vector(random_in_unit_sphere())
+pub fn random_unit_vector(rng: &mut RandGen) -> Vec3 {
+    unit_vector(random_in_unit_sphere(rng))
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    let normal: Normal<f64> = Normal::new(0.0, 1.0).unwrap();
-    let p = Vec3 {
-        e: [
-            normal.sample(&mut rand::thread_rng()),
-            normal.sample(&mut rand::thread_rng()),
-            0.0,
-        ],
+// Shirley and Chiu's concentric mapping, which keeps strata of the square
+// compact on the disk.
+pub fn random_in_unit_disk(u: (f64, f64)) -> Vec3 {
+    let a = 2.0 * u.0 - 1.0;
+    let b = 2.0 * u.1 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
     };
-    let p = unit_vector(p);
-    let u: f64 = random_double();
-    p * u.sqrt()
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
-pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
-    let r1 = random_double();
-    let r2 = random_double();
-    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+pub fn random_to_sphere(radius: f64, distance_squared: f64, u: (f64, f64)) -> Vec3 {
+    let (r1, r2) = u;
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).max(0.0).sqrt() - 1.0);
 
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * (1.0 - z * z).sqrt();